@@ -277,6 +277,7 @@ impl ColorDemo {
                         MouseButton::Left,
                         _,
                         coords,
+                        _,
                     ) => self.handle_click(coords),
                     InputEvent::Repaint => self.repaint(),
                     _ => (),
@@ -0,0 +1,287 @@
+use std::any::Any;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use input::{ButtonMotion, Event, InputEvent, Mods, MouseButton};
+use Coords;
+
+/// Mouse gestures, recognized by [`Gestures`](struct.Gestures.html)
+/// from mouse events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GestureEvent {
+    /// A button was pressed and released without moving; the count is
+    /// 1, 2 or 3 for single, double or triple clicks.
+    Click(MouseButton, Mods, Coords, u8),
+    /// The mouse moved with a button held down, from where the button
+    /// was pressed.
+    DragStart(MouseButton, Mods, Coords),
+    DragMove(MouseButton, Mods, Coords),
+    /// The button was released.
+    DragEnd(MouseButton, Mods, Coords),
+}
+
+impl Event for GestureEvent {
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Recognizes clicks and drags from mouse events.
+///
+/// Events can be passed to `event` one at a time, or `wrap` can be
+/// used to add gesture events to an input channel.
+pub struct Gestures {
+    interval: Duration,
+    tolerance: u16,
+    // The button, position, time and count of the last press that may
+    // be part of a multiple click.
+    last_click: Option<(MouseButton, Coords, Instant, u8)>,
+    pressed: Option<(MouseButton, Mods, Coords)>,
+    dragging: bool,
+}
+
+impl Default for Gestures {
+    fn default() -> Gestures {
+        Gestures::new()
+    }
+}
+
+impl Gestures {
+    /// Clicks count as a multiple click when they are at most 500
+    /// milliseconds and one cell apart.
+    pub fn new() -> Gestures {
+        Gestures {
+            interval: Duration::from_millis(500),
+            tolerance: 1,
+            last_click: None,
+            pressed: None,
+            dragging: false,
+        }
+    }
+
+    /// Sets the longest time between presses of a multiple click.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Sets how many rows and columns apart the presses of a multiple
+    /// click can be.
+    pub fn set_tolerance(&mut self, tolerance: u16) {
+        self.tolerance = tolerance;
+    }
+
+    /// Returns a channel with the events from `rx`, each mouse event
+    /// followed by any gesture events it completes.
+    pub fn wrap(
+        mut self,
+        rx: Receiver<Box<Event>>,
+    ) -> Receiver<Box<Event>> {
+        let (tx, wrapped) = channel();
+        thread::spawn(move || {
+            for evt in rx.iter() {
+                let gestures = match evt.as_any().downcast_ref() {
+                    Some(input) => self.event(input),
+                    None => Vec::new(),
+                };
+                if tx.send(evt).is_err() {
+                    return;
+                }
+                for gesture in gestures {
+                    if tx.send(Box::new(gesture)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        wrapped
+    }
+
+    /// Returns the gesture events completed by `evt`.
+    pub fn event(&mut self, evt: &InputEvent) -> Vec<GestureEvent> {
+        self.event_at(evt, Instant::now())
+    }
+
+    fn event_at(
+        &mut self,
+        evt: &InputEvent,
+        now: Instant,
+    ) -> Vec<GestureEvent> {
+        use self::GestureEvent::*;
+
+        match *evt {
+            InputEvent::Mouse(ButtonMotion::Press, btn, mods, coords, _) => {
+                self.press(btn, mods, coords, now);
+                Vec::new()
+            }
+            InputEvent::Mouse(ButtonMotion::Release, btn, _, coords, _) => {
+                // Only some encodings say which button was released.
+                let (button, mods, start) = match self.pressed {
+                    Some(pressed)
+                        if btn == MouseButton::Unknown || btn == pressed.0 =>
+                    {
+                        pressed
+                    }
+                    _ => return Vec::new(),
+                };
+                self.pressed = None;
+                if self.dragging {
+                    self.dragging = false;
+                    vec![DragEnd(button, mods, coords)]
+                } else if coords != start {
+                    // Without motion reporting, a drag is only seen
+                    // when the button is released.
+                    self.last_click = None;
+                    vec![
+                        DragStart(button, mods, start),
+                        DragEnd(button, mods, coords),
+                    ]
+                } else {
+                    let count = self.last_click.map_or(1, |click| click.3);
+                    vec![Click(button, mods, start, count)]
+                }
+            }
+            InputEvent::MouseDrag(_, _, coords, _)
+            | InputEvent::MouseMove(_, coords, _) => {
+                let (button, mods, start) = match self.pressed {
+                    Some(pressed) => pressed,
+                    None => return Vec::new(),
+                };
+                if self.dragging {
+                    vec![DragMove(button, mods, coords)]
+                } else if coords != start {
+                    self.dragging = true;
+                    self.last_click = None;
+                    vec![
+                        DragStart(button, mods, start),
+                        DragMove(button, mods, coords),
+                    ]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn press(
+        &mut self,
+        button: MouseButton,
+        mods: Mods,
+        coords: Coords,
+        now: Instant,
+    ) {
+        if self.pressed.is_some() {
+            return;
+        }
+        let count = match self.last_click {
+            Some((b, (x, y), time, count))
+                if b == button && now.duration_since(time) <= self.interval
+                    && diff(x, coords.0) <= self.tolerance
+                    && diff(y, coords.1) <= self.tolerance =>
+            {
+                count % 3 + 1
+            }
+            _ => 1,
+        };
+        self.last_click = Some((button, coords, now, count));
+        self.pressed = Some((button, mods, coords));
+        self.dragging = false;
+    }
+}
+
+fn diff(a: u16, b: u16) -> u16 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use input::{ButtonMotion, InputEvent, Mods, MouseButton};
+    use super::{GestureEvent, Gestures};
+
+    fn press(x: u16) -> InputEvent {
+        let (b, m) = (MouseButton::Left, Mods::empty());
+        InputEvent::Mouse(ButtonMotion::Press, b, m, (x, 0), None)
+    }
+
+    fn release(x: u16) -> InputEvent {
+        let (b, m) = (MouseButton::Unknown, Mods::empty());
+        InputEvent::Mouse(ButtonMotion::Release, b, m, (x, 0), None)
+    }
+
+    fn click(x: u16, count: u8) -> Vec<GestureEvent> {
+        let (b, m) = (MouseButton::Left, Mods::empty());
+        vec![GestureEvent::Click(b, m, (x, 0), count)]
+    }
+
+    #[test]
+    fn clicks() {
+        let mut g = Gestures::new();
+        let t = Instant::now();
+        let ms = Duration::from_millis;
+
+        assert!(g.event_at(&press(5), t).is_empty());
+        assert_eq!(click(5, 1), g.event_at(&release(5), t + ms(50)));
+        g.event_at(&press(6), t + ms(300));
+        assert_eq!(click(6, 2), g.event_at(&release(6), t + ms(350)));
+        g.event_at(&press(6), t + ms(600));
+        assert_eq!(click(6, 3), g.event_at(&release(6), t + ms(650)));
+        g.event_at(&press(6), t + ms(900));
+        assert_eq!(click(6, 1), g.event_at(&release(6), t + ms(950)));
+        g.event_at(&press(6), t + ms(1500));
+        assert_eq!(click(6, 1), g.event_at(&release(6), t + ms(1550)));
+
+        g.set_tolerance(0);
+        g.event_at(&press(7), t + ms(1600));
+        assert_eq!(click(7, 1), g.event_at(&release(7), t + ms(1650)));
+    }
+
+    #[test]
+    fn drags() {
+        use super::GestureEvent::*;
+
+        let mut g = Gestures::new();
+        let t = Instant::now();
+        let (b, m) = (MouseButton::Left, Mods::empty());
+
+        g.event_at(&press(1), t);
+        let drag = InputEvent::MouseDrag(b, m, (1, 0), None);
+        assert!(g.event_at(&drag, t).is_empty());
+        let drag = InputEvent::MouseDrag(b, m, (3, 0), None);
+        let expected = vec![DragStart(b, m, (1, 0)), DragMove(b, m, (3, 0))];
+        assert_eq!(expected, g.event_at(&drag, t));
+        let drag = InputEvent::MouseMove(m, (4, 1), None);
+        assert_eq!(vec![DragMove(b, m, (4, 1))], g.event_at(&drag, t));
+        assert_eq!(vec![DragEnd(b, m, (4, 0))], g.event_at(&release(4), t));
+        let mv = InputEvent::MouseMove(m, (5, 0), None);
+        assert!(g.event_at(&mv, t).is_empty());
+
+        g.event_at(&press(4), t);
+        let expected = vec![DragStart(b, m, (4, 0)), DragEnd(b, m, (8, 0))];
+        assert_eq!(expected, g.event_at(&release(8), t));
+        g.event_at(&press(8), t);
+        assert_eq!(click(8, 1), g.event_at(&release(8), t));
+    }
+
+    #[test]
+    fn other_button_released() {
+        let mut g = Gestures::new();
+        let t = Instant::now();
+        let m = Mods::empty();
+
+        g.event_at(&press(2), t);
+        let right = MouseButton::Right;
+        let release_right =
+            InputEvent::Mouse(ButtonMotion::Release, right, m, (2, 0), None);
+        assert!(g.event_at(&release_right, t).is_empty());
+        let left = MouseButton::Left;
+        let release_left =
+            InputEvent::Mouse(ButtonMotion::Release, left, m, (2, 0), None);
+        assert_eq!(click(2, 1), g.event_at(&release_left, t));
+    }
+}
@@ -7,10 +7,17 @@ mod platform;
 #[cfg(not(windows))]
 #[path = "unix/mod.rs"]
 mod platform;
+mod gesture;
+
+pub use self::gesture::{GestureEvent, Gestures};
 
 pub(crate) use self::platform::start_threads;
+#[cfg(not(windows))]
+pub(crate) use self::platform::{read_inline, start_signals, EventLoop,
+                                InputThread, Reader, Reply, Shared};
 #[cfg(windows)]
-pub(crate) use self::platform::Resizer;
+pub(crate) use self::platform::{set_inline_rows, set_mouse_mode, start_polling,
+                                ConsoleReader, Resizer};
 
 use Coords;
 
@@ -35,6 +42,57 @@ pub enum Key {
     F10,
     F11,
     F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    F26,
+    F27,
+    F28,
+    F29,
+    F30,
+    F31,
+    F32,
+    F33,
+    F34,
+    F35,
+    F36,
+    F37,
+    F38,
+    F39,
+    F40,
+    F41,
+    F42,
+    F43,
+    F44,
+    F45,
+    F46,
+    F47,
+    F48,
+    F49,
+    F50,
+    F51,
+    F52,
+    F53,
+    F54,
+    F55,
+    F56,
+    F57,
+    F58,
+    F59,
+    F60,
+    F61,
+    F62,
+    F63,
     BS,
     Tab,
     Enter,
@@ -48,6 +106,43 @@ pub enum Key {
     Down,
     Left,
     Right,
+    BackTab,
+    // The keypad keys outside the arrow cross, and its center.
+    KpUpperLeft,
+    KpUpperRight,
+    KpCenter,
+    KpLowerLeft,
+    KpLowerRight,
+    KpEnter,
+    Begin,
+    Print,
+    Pause,
+    Menu,
+    Help,
+    Undo,
+    Redo,
+    Cancel,
+    Close,
+    Copy,
+    Find,
+    Open,
+    Save,
+    Select,
+    Refresh,
+    Exit,
+    MediaPlay,
+    MediaPause,
+    MediaPlayPause,
+    MediaReverse,
+    MediaStop,
+    MediaFastForward,
+    MediaRewind,
+    MediaNext,
+    MediaPrev,
+    MediaRecord,
+    VolumeDown,
+    VolumeUp,
+    VolumeMute,
 }
 
 #[allow(dead_code)]
@@ -63,6 +158,77 @@ impl Key {
     fn empty() -> Key {
         Key::Char('\x00', [0, 0, 0, 0], 0)
     }
+
+    // Function key `n`, from F1 to F63.
+    fn function(n: u8) -> Option<Key> {
+        let key = match n {
+            1 => Key::F1,
+            2 => Key::F2,
+            3 => Key::F3,
+            4 => Key::F4,
+            5 => Key::F5,
+            6 => Key::F6,
+            7 => Key::F7,
+            8 => Key::F8,
+            9 => Key::F9,
+            10 => Key::F10,
+            11 => Key::F11,
+            12 => Key::F12,
+            13 => Key::F13,
+            14 => Key::F14,
+            15 => Key::F15,
+            16 => Key::F16,
+            17 => Key::F17,
+            18 => Key::F18,
+            19 => Key::F19,
+            20 => Key::F20,
+            21 => Key::F21,
+            22 => Key::F22,
+            23 => Key::F23,
+            24 => Key::F24,
+            25 => Key::F25,
+            26 => Key::F26,
+            27 => Key::F27,
+            28 => Key::F28,
+            29 => Key::F29,
+            30 => Key::F30,
+            31 => Key::F31,
+            32 => Key::F32,
+            33 => Key::F33,
+            34 => Key::F34,
+            35 => Key::F35,
+            36 => Key::F36,
+            37 => Key::F37,
+            38 => Key::F38,
+            39 => Key::F39,
+            40 => Key::F40,
+            41 => Key::F41,
+            42 => Key::F42,
+            43 => Key::F43,
+            44 => Key::F44,
+            45 => Key::F45,
+            46 => Key::F46,
+            47 => Key::F47,
+            48 => Key::F48,
+            49 => Key::F49,
+            50 => Key::F50,
+            51 => Key::F51,
+            52 => Key::F52,
+            53 => Key::F53,
+            54 => Key::F54,
+            55 => Key::F55,
+            56 => Key::F56,
+            57 => Key::F57,
+            58 => Key::F58,
+            59 => Key::F59,
+            60 => Key::F60,
+            61 => Key::F61,
+            62 => Key::F62,
+            63 => Key::F63,
+            _ => return None,
+        };
+        Some(key)
+    }
 }
 
 impl fmt::Display for Key {
@@ -84,15 +250,31 @@ bitflags! {
         const ALT = 0b010;
         const CTRL = 0b100;
         const CTRL_ALT = Self::CTRL.bits | Self::ALT.bits;
+        // Only reported with the kitty keyboard protocol.
+        const SUPER = 0b1000;
+        const HYPER = 0b1_0000;
+        const META = 0b10_0000;
+        const CAPS_LOCK = 0b100_0000;
+        const NUM_LOCK = 0b1000_0000;
     }
 }
 
 // 0b001 = Shift
 // 0b010 = Alt
 // 0b100 = Control
+// (The lock states are not displayed.)
 impl fmt::Display for Mods {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let val = match self.bits {
+        if self.contains(Mods::SUPER) {
+            write!(f, "Super-")?;
+        }
+        if self.contains(Mods::HYPER) {
+            write!(f, "Hyper-")?;
+        }
+        if self.contains(Mods::META) {
+            write!(f, "Meta-")?;
+        }
+        let val = match self.bits & 0b111 {
             1 => "Shift-",
             2 => "Alt-",
             3 => "Alt-Shift-",
@@ -130,6 +312,10 @@ pub enum MouseButton {
     Left,
     Middle,
     Right,
+    Back,
+    Forward,
+    Button10,
+    Button11,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -142,17 +328,29 @@ pub enum ButtonMotion {
 pub enum WheelMotion {
     Up,
     Down,
+    Left,
+    Right,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum InputEvent {
     Repaint,
     Interrupt,
     Break,
-    Mouse(ButtonMotion, MouseButton, Mods, Coords),
-    MouseWheel(WheelMotion, Mods, Coords),
-    MouseMove(Mods, Coords),
+    Suspend,
+    // The last field of mouse events is the position in pixels, when
+    // pixel reporting is enabled.
+    Mouse(ButtonMotion, MouseButton, Mods, Coords, Option<Coords>),
+    MouseWheel(WheelMotion, Mods, Coords, Option<Coords>),
+    MouseMove(Mods, Coords, Option<Coords>),
+    // Movement with a button held down.
+    MouseDrag(MouseButton, Mods, Coords, Option<Coords>),
     Key(Key, Mods),
+    // Only reported with the kitty keyboard protocol.
+    KeyRepeat(Key, Mods),
+    KeyRelease(Key, Mods),
+    Paste(String),
+    Focus(bool),
 }
 
 impl Event for InputEvent {
@@ -0,0 +1,269 @@
+use std::char;
+use input::{InputEvent, Key, Mods};
+
+// Extended key reports, from the kitty keyboard protocol, have the
+// form "CSI code[:shifted[:base]];mods[:event][;text] u" or, for keys
+// with legacy sequences, "CSI num;mods[:event] ~" and
+// "CSI 1;mods[:event] <letter>". With xterm's modifyOtherKeys, they
+// are "CSI 27;mods;code ~" (or "CSI code;mods u", if formatOtherKeys
+// is set).
+pub(super) enum Decoded {
+    Event(InputEvent),
+    // A key report for a key that has no `Key`.
+    Ignore,
+}
+
+// Decodes a CSI sequence, given its parameters and final byte, as an
+// extended key report. Returns `None` for anything else (such as
+// mouse reports), which is left to the key and mouse parsers.
+pub(super) fn parse_key(params: &[u8], fin: u8) -> Option<Decoded> {
+    if params.iter().any(|&b| b < b'0' || b > b';') {
+        return None;
+    }
+    let mut fields = params.split(|&b| b == b';');
+    let mut keys = fields.next().unwrap_or(b"").split(|&b| b == b':');
+    let code = parse_num(keys.next().unwrap_or(b""), 1)?;
+    let shifted = parse_num(keys.next().unwrap_or(b""), 0)?;
+    let mut mod_fields = fields.next().unwrap_or(b"").split(|&b| b == b':');
+    let mods = parse_num(mod_fields.next().unwrap_or(b""), 1)?;
+    let event = parse_num(mod_fields.next().unwrap_or(b""), 1)?;
+    let other = fields.next();
+    if mods == 0 || mods > 0x100 || event == 0 || event > 3 {
+        return None;
+    }
+    let mut mods = Mods::from_bits_truncate((mods - 1) as u8);
+    let key = match fin {
+        b'u' => if shifted != 0 && mods.contains(Mods::SHIFT) {
+            mods.remove(Mods::SHIFT);
+            code_key(shifted)
+        } else {
+            code_key(code)
+        },
+        b'~' => match other {
+            Some(other) if code == 27 => code_key(parse_num(other, 0)?),
+            _ => tilde_key(code)?,
+        },
+        _ if code == 1 => letter_key(fin)?,
+        _ => return None,
+    };
+    let key = match key {
+        Some(key) => key,
+        None => return Some(Decoded::Ignore),
+    };
+    Some(Decoded::Event(match event {
+        1 => InputEvent::Key(key, mods),
+        2 => InputEvent::KeyRepeat(key, mods),
+        _ => InputEvent::KeyRelease(key, mods),
+    }))
+}
+
+// A missing number is `default`.
+fn parse_num(bytes: &[u8], default: u32) -> Option<u32> {
+    if bytes.is_empty() {
+        return Some(default);
+    }
+    if bytes.len() > 7 {
+        return None;
+    }
+    let mut n = 0u32;
+    for &b in bytes {
+        if b < b'0' || b > b'9' {
+            return None;
+        }
+        n = n * 10 + u32::from(b - b'0');
+    }
+    Some(n)
+}
+
+// The key for a Unicode code point; kitty reports keys without one
+// (such as F13, or the keypad) using code points in the private use
+// area.
+fn code_key(code: u32) -> Option<Key> {
+    match code {
+        9 => Some(Key::Tab),
+        13 => Some(Key::Enter),
+        27 => Some(Key::Esc),
+        8 | 127 => Some(Key::BS),
+        c if c >= 0xe000 && c <= 0xf8ff => private_key(c),
+        c if c < 32 => None,
+        c => {
+            let c = char::from_u32(c)?;
+            let mut bytes = [0u8; 4];
+            let len = c.encode_utf8(&mut bytes).len();
+            Some(Key::Char(c, bytes, len))
+        }
+    }
+}
+
+// The keys kitty reports with private use code points. Modifier and
+// lock keys are not reported on their own.
+fn private_key(code: u32) -> Option<Key> {
+    const F13: u32 = 57376;
+    const KP_0: u32 = 57399;
+    const MEDIA: [Key; 13] = [
+        Key::MediaPlay,
+        Key::MediaPause,
+        Key::MediaPlayPause,
+        Key::MediaReverse,
+        Key::MediaStop,
+        Key::MediaFastForward,
+        Key::MediaRewind,
+        Key::MediaNext,
+        Key::MediaPrev,
+        Key::MediaRecord,
+        Key::VolumeDown,
+        Key::VolumeUp,
+        Key::VolumeMute,
+    ];
+    let key = match code {
+        57361 => Key::Print,
+        57362 => Key::Pause,
+        57363 => Key::Menu,
+        c if c >= F13 && c < F13 + 23 => Key::function(13 + (c - F13) as u8)?,
+        c if c >= KP_0 && c < KP_0 + 10 => {
+            Key::ascii(b'0' + (c - KP_0) as u8)
+        }
+        57409 => Key::ascii(b'.'),
+        57410 => Key::ascii(b'/'),
+        57411 => Key::ascii(b'*'),
+        57412 => Key::ascii(b'-'),
+        57413 => Key::ascii(b'+'),
+        57414 => Key::KpEnter,
+        57415 => Key::ascii(b'='),
+        57416 => Key::ascii(b','),
+        57417 => Key::Left,
+        57418 => Key::Right,
+        57419 => Key::Up,
+        57420 => Key::Down,
+        57421 => Key::KpUpperRight,
+        57422 => Key::KpLowerRight,
+        57423 => Key::KpUpperLeft,
+        57424 => Key::KpLowerLeft,
+        57425 => Key::Ins,
+        57426 => Key::Del,
+        57427 => Key::KpCenter,
+        c if c >= 57428 && c < 57428 + MEDIA.len() as u32 => {
+            MEDIA[(c - 57428) as usize]
+        }
+        _ => return None,
+    };
+    Some(key)
+}
+
+// The key for "CSI num ~"; `None` if it isn't a key at all (such as
+// the start of a bracketed paste).
+fn tilde_key(num: u32) -> Option<Option<Key>> {
+    let key = match num {
+        2 => Key::Ins,
+        3 => Key::Del,
+        5 => Key::PgUp,
+        6 => Key::PgDn,
+        7 => Key::Home,
+        8 => Key::End,
+        11 => Key::F1,
+        12 => Key::F2,
+        13 => Key::F3,
+        14 => Key::F4,
+        15 => Key::F5,
+        17 => Key::F6,
+        18 => Key::F7,
+        19 => Key::F8,
+        20 => Key::F9,
+        21 => Key::F10,
+        23 => Key::F11,
+        24 => Key::F12,
+        29 => Key::Menu,
+        _ => return None,
+    };
+    Some(Some(key))
+}
+
+// The key for "CSI 1;mods <letter>".
+fn letter_key(fin: u8) -> Option<Option<Key>> {
+    let key = match fin {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'P' => Key::F1,
+        b'Q' => Key::F2,
+        b'R' => Key::F3,
+        b'S' => Key::F4,
+        b'E' => Key::KpCenter,
+        _ => return None,
+    };
+    Some(Some(key))
+}
+
+#[cfg(test)]
+mod test {
+    use input::{InputEvent, Key, Mods};
+    use super::{parse_key, Decoded};
+
+    fn event(params: &[u8], fin: u8) -> Option<InputEvent> {
+        match parse_key(params, fin) {
+            Some(Decoded::Event(evt)) => Some(evt),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn kitty_keys() {
+        let ctrl_i = InputEvent::Key(Key::ascii(b'i'), Mods::CTRL);
+        assert_eq!(Some(ctrl_i), event(b"105;5", b'u'));
+        let tab = InputEvent::Key(Key::Tab, Mods::empty());
+        assert_eq!(Some(tab), event(b"9", b'u'));
+        let super_a = InputEvent::Key(Key::ascii(b'a'), Mods::SUPER);
+        assert_eq!(Some(super_a), event(b"97;9", b'u'));
+        let shift_a = InputEvent::Key(Key::ascii(b'A'), Mods::CTRL);
+        assert_eq!(Some(shift_a), event(b"97:65;6", b'u'));
+        let release = InputEvent::KeyRelease(Key::Up, Mods::NUM_LOCK);
+        assert_eq!(Some(release), event(b"1;129:3", b'A'));
+        let repeat = InputEvent::KeyRepeat(Key::F5, Mods::empty());
+        assert_eq!(Some(repeat), event(b"15;1:2", b'~'));
+        let up = InputEvent::Key(Key::Up, Mods::empty());
+        assert_eq!(Some(up), event(b"", b'A'));
+        match parse_key(b"57441", b'u') {
+            Some(Decoded::Ignore) => (),
+            _ => panic!(),
+        }
+        assert!(parse_key(b"200", b'~').is_none());
+        assert!(parse_key(b"<0;1;1", b'M').is_none());
+        assert!(parse_key(b"", b'M').is_none());
+        assert!(parse_key(b"97;5:4", b'u').is_none());
+    }
+
+    #[test]
+    fn modify_other_keys() {
+        let ctrl_shift_a =
+            InputEvent::Key(Key::ascii(b'A'), Mods::CTRL | Mods::SHIFT);
+        assert_eq!(Some(ctrl_shift_a), event(b"27;6;65", b'~'));
+        let ctrl_1 = InputEvent::Key(Key::ascii(b'1'), Mods::CTRL);
+        assert_eq!(Some(ctrl_1), event(b"27;5;49", b'~'));
+        let alt_enter = InputEvent::Key(Key::Enter, Mods::ALT);
+        assert_eq!(Some(alt_enter), event(b"27;3;13", b'~'));
+        let ctrl_semi = InputEvent::Key(Key::ascii(b';'), Mods::CTRL);
+        assert_eq!(Some(ctrl_semi), event(b"59;5", b'u'));
+        assert!(parse_key(b"27;5", b'~').is_none());
+    }
+
+    #[test]
+    fn private_use_keys() {
+        let f13 = InputEvent::Key(Key::F13, Mods::SHIFT);
+        assert_eq!(Some(f13), event(b"57376;2", b'u'));
+        let f35 = InputEvent::Key(Key::F35, Mods::empty());
+        assert_eq!(Some(f35), event(b"57398", b'u'));
+        let kp_7 = InputEvent::Key(Key::ascii(b'7'), Mods::empty());
+        assert_eq!(Some(kp_7), event(b"57406", b'u'));
+        let kp_home = InputEvent::Key(Key::KpUpperLeft, Mods::CTRL);
+        assert_eq!(Some(kp_home), event(b"57423;5", b'u'));
+        let mute = InputEvent::KeyRelease(Key::VolumeMute, Mods::empty());
+        assert_eq!(Some(mute), event(b"57440;1:3", b'u'));
+        let menu = InputEvent::Key(Key::Menu, Mods::empty());
+        assert_eq!(Some(menu), event(b"29", b'~'));
+        let center = InputEvent::Key(Key::KpCenter, Mods::ALT);
+        assert_eq!(Some(center), event(b"1;3", b'E'));
+    }
+}
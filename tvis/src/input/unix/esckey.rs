@@ -1,4 +1,5 @@
 use tinf::{cap, Desc};
+use tinf::cap::UserDef;
 use input::{Key, Mods};
 use super::escmouse::{MOUSE_MAGIC, SGR_MAGIC};
 use super::escmouse::Type as MouseType;
@@ -6,6 +7,10 @@ use super::escmouse::Parser as MouseParser;
 
 type KeyPress = (Key, Mods);
 
+pub(super) const PASTE_MAGIC: usize = 30;
+pub(super) const FOCUS_IN_MAGIC: usize = 40;
+pub(super) const FOCUS_OUT_MAGIC: usize = 41;
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[repr(C)]
 pub(super) struct EscNode {
@@ -37,6 +42,8 @@ enum State {
 pub(super) enum ParseResult {
     Found(KeyPress),
     Mouse(MouseType),
+    Paste,
+    Focus(bool),
     Maybe,
     No,
 }
@@ -52,6 +59,27 @@ impl Parser {
     pub(super) fn new(desc: &Desc) -> Parser {
         let mut nodes: Vec<EscNode> = vec![Default::default()];
         MouseParser::add_mouse_keys(&mut nodes);
+        let ps = desc.get_str_ext(&UserDef::named("PS"));
+        let ps = if ps.len() > 1 && ps[0] == 0x1b {
+            &ps[1..]
+        } else {
+            b"[200~"
+        };
+        Parser::add_key_bytes(
+            &mut nodes,
+            ps,
+            (Key::Char('\x00', [0, 0, 0, 0], PASTE_MAGIC), Mods::empty()),
+        );
+        Parser::add_key_bytes(
+            &mut nodes,
+            b"[I",
+            (Key::Char('\x00', [0, 0, 0, 0], FOCUS_IN_MAGIC), Mods::empty()),
+        );
+        Parser::add_key_bytes(
+            &mut nodes,
+            b"[O",
+            (Key::Char('\x00', [0, 0, 0, 0], FOCUS_OUT_MAGIC), Mods::empty()),
+        );
         // For the keys in APPKEYS, xterm-alikes deviate from their
         // normal behavior for modifier keys; instead of sending, for
         // example ';2' for shift just before the last byte of the
@@ -59,7 +87,9 @@ impl Parser {
         // activate application mode, we add entries that swap SS3 for
         // CSI).
         let xterm_mods = Parser::xterm_mods(desc);
-        for &(c, k) in APPKEYS.iter().filter(|x| !desc[x.0].is_empty()) {
+        let is_esc_seq =
+            |c: cap::String| desc[c].len() > 1 && desc[c][0] == 27;
+        for &(c, k) in APPKEYS.iter().filter(|x| is_esc_seq(x.0)) {
             Parser::add_key_bytes(&mut nodes, &desc[c][1..], k);
             if xterm_mods && desc[c][1] == b'O' {
                 let mut csi = vec![b'['];
@@ -70,9 +100,17 @@ impl Parser {
                 Parser::add_key_bytes(&mut nodes, &csi, k);
             }
         }
-        for &(c, k) in KEYS.iter().filter(|x| !desc[x.0].is_empty()) {
+        for &(c, k) in KEYS.iter().filter(|x| is_esc_seq(x.0)) {
             Parser::add_key_bytes(&mut nodes, &desc[c][1..], k);
         }
+        // Terminals without enough function keys describe F13 and up
+        // as modified versions of the others (for example, xterm's
+        // F13 is Shift-F1), which are already decoded as such.
+        for &(c, k) in FKEYS.iter().filter(|x| is_esc_seq(x.0)) {
+            if !(xterm_mods && Parser::is_modified(&desc[c])) {
+                Parser::add_key_bytes(&mut nodes, &desc[c][1..], k);
+            }
+        }
         Parser {
             nodes,
             state: State::Plain(0),
@@ -130,6 +168,14 @@ impl Parser {
             })
     }
 
+    // Whether `seq` ends with ";<digit><char>", like a key with the
+    // standard modifier scheme.
+    fn is_modified(seq: &[u8]) -> bool {
+        let len = seq.len();
+        len > 3 && seq[len - 3] == b';' && seq[len - 2] >= b'2'
+            && seq[len - 2] <= b'9'
+    }
+
     // Capabilities named like "kXXX" are shifted keys.
     fn is_shifted_key(scap: &cap::String) -> bool {
         let mut bytes = scap.short_name().bytes();
@@ -154,6 +200,9 @@ impl Parser {
                         match k {
                             Key::Char(_, _, MOUSE_MAGIC) => Mouse(Normal),
                             Key::Char(_, _, SGR_MAGIC) => Mouse(SGR),
+                            Key::Char(_, _, PASTE_MAGIC) => Paste,
+                            Key::Char(_, _, FOCUS_IN_MAGIC) => Focus(true),
+                            Key::Char(_, _, FOCUS_OUT_MAGIC) => Focus(false),
                             _ => {
                                 // ignore "meta" bit
                                 let m1 = m1.bits | (m & 7);
@@ -229,6 +278,8 @@ lazy_static! {
             (cap::kf2, (Key::F2, Mods::empty())),
             (cap::kf3, (Key::F3, Mods::empty())),
             (cap::kf4, (Key::F4, Mods::empty())),
+            (cap::kb2, (Key::KpCenter, Mods::empty())),
+            (cap::kbeg, (Key::Begin, Mods::empty())),
         ]
     };
 
@@ -246,6 +297,81 @@ lazy_static! {
             (cap::kf10, (Key::F10, Mods::empty())),
             (cap::kf11, (Key::F11, Mods::empty())),
             (cap::kf12, (Key::F12, Mods::empty())),
+            (cap::kcbt, (Key::BackTab, Mods::empty())),
+            (cap::ka1, (Key::KpUpperLeft, Mods::empty())),
+            (cap::ka3, (Key::KpUpperRight, Mods::empty())),
+            (cap::kc1, (Key::KpLowerLeft, Mods::empty())),
+            (cap::kc3, (Key::KpLowerRight, Mods::empty())),
+            (cap::kent, (Key::KpEnter, Mods::empty())),
+            (cap::kprt, (Key::Print, Mods::empty())),
+            (cap::khlp, (Key::Help, Mods::empty())),
+            (cap::kund, (Key::Undo, Mods::empty())),
+            (cap::krdo, (Key::Redo, Mods::empty())),
+            (cap::kcan, (Key::Cancel, Mods::empty())),
+            (cap::kclo, (Key::Close, Mods::empty())),
+            (cap::kcpy, (Key::Copy, Mods::empty())),
+            (cap::kfnd, (Key::Find, Mods::empty())),
+            (cap::kopn, (Key::Open, Mods::empty())),
+            (cap::ksav, (Key::Save, Mods::empty())),
+            (cap::kslt, (Key::Select, Mods::empty())),
+            (cap::kref, (Key::Refresh, Mods::empty())),
+            (cap::kext, (Key::Exit, Mods::empty())),
+        ]
+    };
+
+    static ref FKEYS: Vec<(cap::String, KeyPress)> = {
+        vec![
+            (cap::kf13, (Key::F13, Mods::empty())),
+            (cap::kf14, (Key::F14, Mods::empty())),
+            (cap::kf15, (Key::F15, Mods::empty())),
+            (cap::kf16, (Key::F16, Mods::empty())),
+            (cap::kf17, (Key::F17, Mods::empty())),
+            (cap::kf18, (Key::F18, Mods::empty())),
+            (cap::kf19, (Key::F19, Mods::empty())),
+            (cap::kf20, (Key::F20, Mods::empty())),
+            (cap::kf21, (Key::F21, Mods::empty())),
+            (cap::kf22, (Key::F22, Mods::empty())),
+            (cap::kf23, (Key::F23, Mods::empty())),
+            (cap::kf24, (Key::F24, Mods::empty())),
+            (cap::kf25, (Key::F25, Mods::empty())),
+            (cap::kf26, (Key::F26, Mods::empty())),
+            (cap::kf27, (Key::F27, Mods::empty())),
+            (cap::kf28, (Key::F28, Mods::empty())),
+            (cap::kf29, (Key::F29, Mods::empty())),
+            (cap::kf30, (Key::F30, Mods::empty())),
+            (cap::kf31, (Key::F31, Mods::empty())),
+            (cap::kf32, (Key::F32, Mods::empty())),
+            (cap::kf33, (Key::F33, Mods::empty())),
+            (cap::kf34, (Key::F34, Mods::empty())),
+            (cap::kf35, (Key::F35, Mods::empty())),
+            (cap::kf36, (Key::F36, Mods::empty())),
+            (cap::kf37, (Key::F37, Mods::empty())),
+            (cap::kf38, (Key::F38, Mods::empty())),
+            (cap::kf39, (Key::F39, Mods::empty())),
+            (cap::kf40, (Key::F40, Mods::empty())),
+            (cap::kf41, (Key::F41, Mods::empty())),
+            (cap::kf42, (Key::F42, Mods::empty())),
+            (cap::kf43, (Key::F43, Mods::empty())),
+            (cap::kf44, (Key::F44, Mods::empty())),
+            (cap::kf45, (Key::F45, Mods::empty())),
+            (cap::kf46, (Key::F46, Mods::empty())),
+            (cap::kf47, (Key::F47, Mods::empty())),
+            (cap::kf48, (Key::F48, Mods::empty())),
+            (cap::kf49, (Key::F49, Mods::empty())),
+            (cap::kf50, (Key::F50, Mods::empty())),
+            (cap::kf51, (Key::F51, Mods::empty())),
+            (cap::kf52, (Key::F52, Mods::empty())),
+            (cap::kf53, (Key::F53, Mods::empty())),
+            (cap::kf54, (Key::F54, Mods::empty())),
+            (cap::kf55, (Key::F55, Mods::empty())),
+            (cap::kf56, (Key::F56, Mods::empty())),
+            (cap::kf57, (Key::F57, Mods::empty())),
+            (cap::kf58, (Key::F58, Mods::empty())),
+            (cap::kf59, (Key::F59, Mods::empty())),
+            (cap::kf60, (Key::F60, Mods::empty())),
+            (cap::kf61, (Key::F61, Mods::empty())),
+            (cap::kf62, (Key::F62, Mods::empty())),
+            (cap::kf63, (Key::F63, Mods::empty())),
         ]
     };
 }
@@ -378,4 +504,45 @@ mod test {
         assert_eq!(search(&mut with_mods, mf5.0), Some(f5.1));
         assert_eq!(search(&mut with_mods, macf5.0), Some(acf5.1));
     }
+
+    fn fkeys_desc(xterm: bool) -> ::tinf::Desc {
+        use tinf::cap::*;
+        if xterm {
+            desc![
+                kf1 => b"\x1bOP",
+                kf3 => b"\x1bOR",
+                kf13 => b"\x1b[1;2P",
+                kf40 => b"\x1b[1;6R",
+                kRIT => b"\x1b[1;2C",
+                kb2 => b"\x1bOE",
+                kcbt => b"\x1b[Z",
+                kent => b"\x1bOM",
+            ]
+        } else {
+            desc![
+                kf1 => b"\x1b[11~",
+                kf13 => b"\x1b[25~",
+                kent => b"\r",
+            ]
+        }
+    }
+
+    #[test]
+    fn more_keys() {
+        let mut xterm = Parser::new(&fkeys_desc(true));
+        let f1 = Some((Key::F1, Mods::SHIFT));
+        assert_eq!(search(&mut xterm, b"[1;2P"), f1);
+        let f3 = Some((Key::F3, Mods::CTRL | Mods::SHIFT));
+        assert_eq!(search(&mut xterm, b"[1;6R"), f3);
+        let center = Some((Key::KpCenter, Mods::CTRL));
+        assert_eq!(search(&mut xterm, b"[1;5E"), center);
+        let back_tab = Some((Key::BackTab, Mods::empty()));
+        assert_eq!(search(&mut xterm, b"[Z"), back_tab);
+        let enter = Some((Key::KpEnter, Mods::empty()));
+        assert_eq!(search(&mut xterm, b"OM"), enter);
+
+        let mut rxvt = Parser::new(&fkeys_desc(false));
+        let f13 = Some((Key::F13, Mods::empty()));
+        assert_eq!(search(&mut rxvt, b"[25~"), f13);
+    }
 }
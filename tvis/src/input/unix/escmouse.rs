@@ -1,3 +1,4 @@
+use Coords;
 use input::{ButtonMotion, InputEvent, Key, Mods, MouseButton, WheelMotion};
 use super::EscNode;
 use super::esckey::Parser as KeyParser;
@@ -10,7 +11,7 @@ pub(super) enum Type {
     Urxvt,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub(super) enum ParseResult {
     Found(InputEvent),
     Maybe,
@@ -72,39 +73,45 @@ impl Parser {
         self.plen = 0;
     }
 
-    pub(super) fn parse(&mut self, byte: u8) -> ParseResult {
+    // Values are bytes, except in normal mode, where they may be
+    // UTF-8 encoded (with mode 1005) to allow coordinates above 223.
+    pub(super) fn parse(&mut self, val: u16) -> ParseResult {
         match self.ty {
-            Type::Normal => self.parse_normal(byte),
-            Type::SGR | Type::Urxvt => self.parse_extended(byte),
+            Type::Normal => self.parse_normal(val),
+            Type::SGR | Type::Urxvt => if val < 0x80 {
+                self.parse_extended(val as u8)
+            } else {
+                ParseResult::No
+            },
             _ => unreachable!(),
         }
     }
 
-    fn parse_normal(&mut self, byte: u8) -> ParseResult {
+    fn parse_normal(&mut self, val: u16) -> ParseResult {
         use self::ParseResult::*;
 
         match self.state {
             State::B => {
-                if byte < 32 {
+                if val < 32 || val > 0xff {
                     return No;
                 }
-                if !self.x10_button(byte) {
+                if !self.x10_button((val - 32) as u8) {
                     return No;
                 }
                 self.state = State::X;
             }
             State::X => {
-                if byte < 33 {
+                if val < 33 {
                     return No;
                 }
-                self.x = u16::from(byte - 33);
+                self.x = val - 32;
                 self.state = State::Y;
             }
             State::Y => {
-                if byte < 33 {
+                if val < 33 {
                     return No;
                 }
-                self.set_coords(u16::from(byte - 33));
+                self.set_coords(val - 32);
                 return self.mouse_event();
             }
         }
@@ -132,8 +139,14 @@ impl Parser {
             if self.plen == 0 {
                 return false;
             }
+            // Urxvt adds 32 to the button, like the normal encoding.
             let param = self.param as u8;
-            if !self.x10_button(param + if sgr { 32 } else { 0 }) {
+            let cb = match (sgr, param) {
+                (true, p) => p,
+                (false, p) if p >= 32 => p - 32,
+                _ => return false,
+            };
+            if !self.x10_button(cb) {
                 return false;
             }
             self.next_state(State::X);
@@ -173,12 +186,13 @@ impl Parser {
             let param = self.param;
             self.set_coords(param);
             if sgr {
-                match (byte, self.evt) {
-                    (b'm', Mouse(Press, b, ms, cs)) => {
-                        self.evt = Mouse(ButtonMotion::Release, b, ms, cs);
+                match (byte, &self.evt) {
+                    (b'm', &Mouse(Press, b, ms, cs, _)) => {
+                        self.evt =
+                            Mouse(ButtonMotion::Release, b, ms, cs, None);
                     }
-                    (b'M', Mouse(Release, _, ms, cs)) => {
-                        self.evt = MouseMove(ms, cs);
+                    (b'M', &Mouse(Release, _, ms, cs, _)) => {
+                        self.evt = MouseMove(ms, cs, None);
                     }
                     _ => (),
                 }
@@ -201,56 +215,92 @@ impl Parser {
         self.param <= max
     }
 
-    fn x10_button(&mut self, byte: u8) -> bool {
+    // The button code is the button number (0-2 for the main buttons,
+    // 64-67 for wheel motions and 128-131 for buttons 8-11), plus 4, 8
+    // and 16 for Shift, Alt and Ctrl, and 32 for motion.
+    fn x10_button(&mut self, cb: u8) -> bool {
         use self::InputEvent::*;
         use self::MouseButton::*;
         use self::ButtonMotion::*;
 
-        let mods = Mods::from_bits((byte >> 2) & 0b111).unwrap();
-        let byte = byte & 0b1110_0011;
-        if byte > 95 {
-            if byte == 96 {
-                self.evt = MouseWheel(WheelMotion::Up, mods, (0, 0));
-            } else if byte == 97 {
-                self.evt = MouseWheel(WheelMotion::Down, mods, (0, 0));
-            } else if self.ty == Type::Urxvt && (byte == 128 || byte == 129) {
+        let mods = Mods::from_bits((cb >> 2) & 0b111).unwrap();
+        let motion = cb & 32 != 0;
+        let n = (cb & 0b11) as usize;
+        let button = match cb & 0b1100_0000 {
+            0 if n == 3 => Unknown,
+            0 => [Left, Middle, Right][n],
+            64 => {
                 // Urxvt reports mouse movements after a wheel event
                 // as though the wheel motion was still "pressed",
                 // until another mouse button is pressed.
-                self.evt = MouseMove(mods, (0, 0));
-            } else {
-                return false;
+                self.evt = if motion {
+                    MouseMove(mods, (0, 0), None)
+                } else {
+                    let wm = [
+                        WheelMotion::Up,
+                        WheelMotion::Down,
+                        WheelMotion::Left,
+                        WheelMotion::Right,
+                    ];
+                    MouseWheel(wm[n], mods, (0, 0), None)
+                };
+                return true;
             }
-        } else if byte > 63 {
-            self.evt = MouseMove(mods, (0, 0));
-        } else {
-            self.evt = match byte & 0b11 {
-                0 => Mouse(Press, Left, mods, (0, 0)),
-                1 => Mouse(Press, Middle, mods, (0, 0)),
-                2 => Mouse(Press, Right, mods, (0, 0)),
-                3 => Mouse(Release, Unknown, mods, (0, 0)),
-                _ => unreachable!(),
-            }
-        }
+            128 => [Back, Forward, Button10, Button11][n],
+            _ => return false,
+        };
+        self.evt = match (motion, button) {
+            (true, Unknown) => MouseMove(mods, (0, 0), None),
+            (true, b) => MouseDrag(b, mods, (0, 0), None),
+            (false, Unknown) => Mouse(Release, Unknown, mods, (0, 0), None),
+            (false, b) => Mouse(Press, b, mods, (0, 0), None),
+        };
         true
     }
 
+    // Coordinates are reported starting from 1.
     fn set_coords(&mut self, y: u16) {
-        use self::InputEvent::*;
-
+        if self.x == 0 || y == 0 {
+            return;
+        }
         let coords = (self.x - 1, y - 1);
-        let evt = match self.evt {
-            Mouse(m, b, ms, _) => Mouse(m, b, ms, coords),
-            MouseMove(ms, _) => MouseMove(ms, coords),
-            MouseWheel(wm, ms, _) => MouseWheel(wm, ms, coords),
-            _ => return,
-        };
-        self.evt = evt;
+        self.evt = with_coords(&self.evt, coords, None);
     }
 
     fn mouse_event(&mut self) -> ParseResult {
         let ty = self.ty;
         self.reset(ty);
-        ParseResult::Found(self.evt)
+        ParseResult::Found(self.evt.clone())
+    }
+}
+
+// The coordinates of a mouse event.
+pub(super) fn coords(evt: &InputEvent) -> Option<Coords> {
+    use self::InputEvent::*;
+
+    match *evt {
+        Mouse(_, _, _, cs, _)
+        | MouseWheel(_, _, cs, _)
+        | MouseMove(_, cs, _)
+        | MouseDrag(_, _, cs, _) => Some(cs),
+        _ => None,
+    }
+}
+
+// A mouse event with its coordinates (and position in pixels)
+// replaced.
+pub(super) fn with_coords(
+    evt: &InputEvent,
+    coords: Coords,
+    pixels: Option<Coords>,
+) -> InputEvent {
+    use self::InputEvent::*;
+
+    match *evt {
+        Mouse(m, b, ms, _, _) => Mouse(m, b, ms, coords, pixels),
+        MouseWheel(wm, ms, _, _) => MouseWheel(wm, ms, coords, pixels),
+        MouseMove(ms, _, _) => MouseMove(ms, coords, pixels),
+        MouseDrag(b, ms, _, _) => MouseDrag(b, ms, coords, pixels),
+        ref evt => evt.clone(),
     }
 }
@@ -0,0 +1,177 @@
+// Replies to queries sent by the terminal module, which are routed
+// back to it instead of being sent as input events.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Reply {
+    // OSC 4: a palette entry.
+    Palette(u8, (u8, u8, u8)),
+    // OSC 10/11/12: the default foreground (0), default background
+    // (1), or cursor (2) color.
+    Dynamic(u8, (u8, u8, u8)),
+    // CPR: the cursor's row and column, as reported.
+    Cursor(u16, u16),
+    // The kitty keyboard protocol's current flags.
+    Keyboard(u8),
+    // Primary device attributes (which every terminal should send).
+    Attributes,
+    // DECRPM: a private mode and its status (0 if the mode is not
+    // recognized, 1 if set, 2 if reset, 3 or 4 if permanently set or
+    // reset).
+    Mode(u16, u8),
+}
+
+// Parses the body of an OSC sequence (without the introducer or
+// terminator).
+pub(super) fn parse_osc(bytes: &[u8]) -> Option<Reply> {
+    let mut fields = bytes.split(|&b| b == b';');
+    let ps = parse_num(fields.next()?)?;
+    match ps {
+        4 => {
+            let i = parse_num(fields.next()?)?;
+            if i > 255 {
+                return None;
+            }
+            let rgb = parse_rgb(fields.next()?)?;
+            Some(Reply::Palette(i as u8, rgb))
+        }
+        10 | 11 | 12 => {
+            let rgb = parse_rgb(fields.next()?)?;
+            Some(Reply::Dynamic((ps - 10) as u8, rgb))
+        }
+        _ => None,
+    }
+}
+
+// Parses a CSI sequence, given its parameters and final byte, if it
+// is a reply; cursor position reports are only recognized if `cpr`
+// is true, since they look like modified F3 keys.
+pub(super) fn parse_csi(params: &[u8], fin: u8, cpr: bool) -> Option<Reply> {
+    match (params.first(), fin) {
+        (Some(&b'?'), b'u') => {
+            let flags = parse_num(&params[1..])?;
+            if flags > 0xff {
+                return None;
+            }
+            Some(Reply::Keyboard(flags as u8))
+        }
+        (Some(&b'?'), b'c') => Some(Reply::Attributes),
+        (Some(&b'?'), b'y') if params.ends_with(b"$") => {
+            parse_mode(&params[1..params.len() - 1])
+        }
+        (_, b'R') if cpr => parse_cpr(params),
+        _ => None,
+    }
+}
+
+// Parses the body of a cursor position report ("row;col", without the
+// CSI or the final R).
+fn parse_cpr(bytes: &[u8]) -> Option<Reply> {
+    let mut fields = bytes.split(|&b| b == b';');
+    let row = parse_num(fields.next()?)?;
+    let col = parse_num(fields.next()?)?;
+    if fields.next().is_some() || row > 0xffff || col > 0xffff {
+        return None;
+    }
+    Some(Reply::Cursor(row as u16, col as u16))
+}
+
+// Parses the body of a mode report ("mode;status").
+fn parse_mode(bytes: &[u8]) -> Option<Reply> {
+    let mut fields = bytes.split(|&b| b == b';');
+    let mode = parse_num(fields.next()?)?;
+    let status = parse_num(fields.next()?)?;
+    if fields.next().is_some() || mode > 0xffff || status > 4 {
+        return None;
+    }
+    Some(Reply::Mode(mode as u16, status as u8))
+}
+
+fn parse_num(bytes: &[u8]) -> Option<u32> {
+    if bytes.is_empty() || bytes.len() > 5 {
+        return None;
+    }
+    let mut n = 0u32;
+    for &b in bytes {
+        if b < b'0' || b > b'9' {
+            return None;
+        }
+        n = n * 10 + u32::from(b - b'0');
+    }
+    Some(n)
+}
+
+// Parses "rgb:r/g/b", where each component has one to four hex
+// digits, to 8-bit components.
+fn parse_rgb(bytes: &[u8]) -> Option<(u8, u8, u8)> {
+    if !bytes.starts_with(b"rgb:") {
+        return None;
+    }
+    let mut parts = bytes[4..].split(|&b| b == b'/');
+    let r = parse_component(parts.next()?)?;
+    let g = parse_component(parts.next()?)?;
+    let b = parse_component(parts.next()?)?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((r, g, b))
+}
+
+fn parse_component(bytes: &[u8]) -> Option<u8> {
+    if bytes.is_empty() || bytes.len() > 4 {
+        return None;
+    }
+    let mut n = 0u32;
+    for &b in bytes {
+        let digit = (b as char).to_digit(16)?;
+        n = n * 16 + digit;
+    }
+    let max = (1u32 << (4 * bytes.len())) - 1;
+    Some(((n * 255 + max / 2) / max) as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_cpr, parse_csi, parse_osc, Reply};
+
+    #[test]
+    fn osc_colors() {
+        assert_eq!(
+            Some(Reply::Dynamic(1, (0xff, 0x80, 0x00))),
+            parse_osc(b"11;rgb:ffff/8080/0000")
+        );
+        assert_eq!(
+            Some(Reply::Palette(3, (0xff, 0x11, 0x00))),
+            parse_osc(b"4;3;rgb:f/1/0")
+        );
+        assert_eq!(
+            Some(Reply::Dynamic(0, (0x12, 0x34, 0x56))),
+            parse_osc(b"10;rgb:12/34/56")
+        );
+        assert_eq!(None, parse_osc(b"11;rgba:ffff/8080/0000/ffff"));
+        assert_eq!(None, parse_osc(b"4;256;rgb:f/1/0"));
+        assert_eq!(None, parse_osc(b"2;title"));
+    }
+
+    #[test]
+    fn cursor_report() {
+        assert_eq!(Some(Reply::Cursor(24, 80)), parse_cpr(b"24;80"));
+        assert_eq!(Some(Reply::Cursor(1, 2)), parse_cpr(b"1;2"));
+        assert_eq!(None, parse_cpr(b"24"));
+        assert_eq!(None, parse_cpr(b"24;80;1"));
+        assert_eq!(None, parse_cpr(b";80"));
+        assert_eq!(None, parse_cpr(b"99999;1"));
+    }
+
+    #[test]
+    fn csi_replies() {
+        assert_eq!(Some(Reply::Keyboard(5)), parse_csi(b"?5", b'u', false));
+        assert_eq!(Some(Reply::Attributes), parse_csi(b"?62;22", b'c', false));
+        assert_eq!(Some(Reply::Cursor(3, 4)), parse_csi(b"3;4", b'R', true));
+        assert_eq!(None, parse_csi(b"3;4", b'R', false));
+        assert_eq!(None, parse_csi(b"97;5", b'u', true));
+        assert_eq!(
+            Some(Reply::Mode(1005, 2)),
+            parse_csi(b"?1005;2$", b'y', false)
+        );
+        assert_eq!(None, parse_csi(b"?1005;2", b'y', false));
+    }
+}
@@ -1,32 +1,96 @@
 #![cfg(not(windows))]
 
-use std::{mem, ptr, thread};
+use std::{cmp, io, mem, ptr, thread};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use libc::{self, c_int};
 use tinf::{cap, Desc};
+use tinf::cap::UserDef;
 
 use input::{Event, InputEvent, Key, Mods};
-use {is_rxvt, Error, Result};
+use term::{EscPolicy, EscTimeout};
+use {is_rxvt, Coords, Error, Result};
 
+mod esccsi;
 mod esckey;
 mod escmouse;
+mod escreply;
 
 use self::esckey::EscNode;
+pub(crate) use self::escreply::Reply;
 
 static mut SIGNAL_FDS: Option<(c_int, c_int)> = None;
 
-// Starts the event loop thread and initializes signal handling.
-pub(crate) fn start_threads(tx: Sender<Box<Event>>) -> Result<()> {
-    init_pipe()?;
-    thread::spawn(|| unsafe {
+// Starts the event loop thread, and if `signals` is true initializes
+// signal handling (which is only done for the process's own terminal).
+pub(crate) fn start_threads(
+    reader: Reader,
+    signals: bool,
+) -> Result<InputThread> {
+    let mut stop_fds: [c_int; 2] = [0; 2];
+    if -1 == unsafe { libc::pipe(stop_fds.as_mut_ptr()) } {
+        return Error::ffi_err("pipe failed");
+    }
+    if signals {
+        if let Err(err) = init_pipe() {
+            unsafe {
+                libc::close(stop_fds[0]);
+                libc::close(stop_fds[1]);
+            }
+            return Err(err);
+        }
+    }
+    let stop_fd = stop_fds[0];
+    let handle = thread::spawn(move || {
         // Prevent signals from being delivered to the event loop thread.
-        let mut set: libc::sigset_t = mem::uninitialized();
-        libc::sigfillset(&mut set);
-        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
-        raw_event_loop(tx);
+        unsafe {
+            let mut set: libc::sigset_t = mem::uninitialized();
+            libc::sigfillset(&mut set);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        }
+        raw_event_loop(reader, signals, stop_fd);
     });
+    if signals {
+        init_signals();
+    }
+    Ok(InputThread {
+        handle: Some(handle),
+        stop_fds: (stop_fds[0], stop_fds[1]),
+    })
+}
+
+// The event loop thread, which is stopped (by writing to a pipe that
+// it selects on) and joined when dropped, so that it no longer reads
+// the terminal's input once the terminal is gone.
+pub(crate) struct InputThread {
+    handle: Option<thread::JoinHandle<()>>,
+    stop_fds: (c_int, c_int),
+}
+
+impl Drop for InputThread {
+    fn drop(&mut self) {
+        unsafe {
+            let byte = 0u8;
+            let ptr = &byte as *const _ as *const libc::c_void;
+            libc::write(self.stop_fds.1, ptr, 1);
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        unsafe {
+            libc::close(self.stop_fds.0);
+            libc::close(self.stop_fds.1);
+        }
+    }
+}
+
+// Initializes signal handling for input read on the calling thread
+// with an `EventLoop`.
+pub(crate) fn start_signals() -> Result<()> {
+    init_pipe()?;
     init_signals();
     Ok(())
 }
@@ -54,6 +118,8 @@ fn init_signals() {
             libc::SIGTERM => 2,
             libc::SIGINT => 3,
             libc::SIGQUIT => 4,
+            libc::SIGTSTP => 5,
+            libc::SIGCONT => 6,
             _ => return,
         };
         unsafe {
@@ -74,88 +140,407 @@ fn init_signals() {
         libc::sigaction(libc::SIGTERM, &sa, ptr::null_mut());
         libc::sigaction(libc::SIGINT, &sa, ptr::null_mut());
         libc::sigaction(libc::SIGQUIT, &sa, ptr::null_mut());
+        libc::sigaction(libc::SIGTSTP, &sa, ptr::null_mut());
+        libc::sigaction(libc::SIGCONT, &sa, ptr::null_mut());
+    }
+}
+
+// Settings shared by a terminal and the reader of its input, which
+// may be on the event loop thread.
+pub(crate) struct Shared {
+    // The terminal's input.
+    fd: c_int,
+    // Whether the terminal is the process's own (and so has signals
+    // and job control).
+    controlling: bool,
+    job_control: AtomicBool,
+    cpr_pending: AtomicBool,
+    extended_keys: AtomicBool,
+    pixel_mouse: AtomicBool,
+    utf8_mouse: AtomicBool,
+    mode_pending: AtomicBool,
+    // The escape timeout in microseconds, or zero for the default.
+    esc_timeout: AtomicUsize,
+    adaptive_esc: AtomicBool,
+    esc_without_alt: AtomicBool,
+    // In inline mode, the number of rows (zero otherwise) and the
+    // screen row of the first, or NO_ORIGIN if it is not known.
+    inline_rows: AtomicUsize,
+    inline_origin: AtomicUsize,
+    // Set when SIGWINCH is read, until the terminal reads its size.
+    resized: AtomicBool,
+}
+
+const NO_ORIGIN: usize = ::std::usize::MAX;
+
+impl Default for Shared {
+    fn default() -> Shared {
+        Shared::new(0, true)
+    }
+}
+
+impl Shared {
+    pub(crate) fn new(fd: c_int, controlling: bool) -> Shared {
+        Shared {
+            fd,
+            controlling,
+            job_control: AtomicBool::new(false),
+            cpr_pending: AtomicBool::new(false),
+            extended_keys: AtomicBool::new(false),
+            pixel_mouse: AtomicBool::new(false),
+            utf8_mouse: AtomicBool::new(false),
+            mode_pending: AtomicBool::new(false),
+            esc_timeout: AtomicUsize::new(0),
+            adaptive_esc: AtomicBool::new(false),
+            esc_without_alt: AtomicBool::new(false),
+            inline_rows: AtomicUsize::new(0),
+            inline_origin: AtomicUsize::new(NO_ORIGIN),
+            resized: AtomicBool::new(false),
+        }
+    }
+
+    // When job control is enabled, Ctrl-Z and SIGTSTP are sent as
+    // InputEvent::Suspend.
+    pub(crate) fn set_job_control(&self, enabled: bool) {
+        self.job_control.store(enabled, Ordering::SeqCst);
+    }
+
+    // While a cursor position report is expected, "CSI row;col R" is
+    // sent as a Reply instead of being parsed as a key (for example,
+    // "CSI 1;2R" is Shift-F3).
+    pub(crate) fn expect_cursor_report(&self, pending: bool) {
+        self.cpr_pending.store(pending, Ordering::SeqCst);
+    }
+
+    // While extended key reports are enabled (or being queried), CSI
+    // sequences are decoded by esccsi before the key parser sees them.
+    pub(crate) fn expect_extended_keys(&self, enabled: bool) {
+        self.extended_keys.store(enabled, Ordering::SeqCst);
+    }
+
+    // While SGR-Pixels mouse reporting is enabled, SGR mouse
+    // coordinates are in pixels, and are converted to cells before
+    // being sent.
+    pub(crate) fn expect_pixel_mouse(&self, enabled: bool) {
+        self.pixel_mouse.store(enabled, Ordering::SeqCst);
+    }
+
+    // While UTF-8 mouse mode (1005) is in effect, coordinates in
+    // normal mouse reports may be two-byte UTF-8 characters.
+    pub(crate) fn expect_utf8_mouse(&self, enabled: bool) {
+        self.utf8_mouse.store(enabled, Ordering::SeqCst);
+    }
+
+    // While a mode report (the reply to DECRQM) is expected, CSI
+    // sequences are checked for replies.
+    pub(crate) fn expect_mode_report(&self, pending: bool) {
+        self.mode_pending.store(pending, Ordering::SeqCst);
+    }
+
+    pub(crate) fn set_esc_timeout(&self, timeout: EscTimeout) {
+        match timeout {
+            EscTimeout::Fixed(timeout) => {
+                let micros = cmp::min(cmp::max(micros(timeout), 1), 999_999);
+                self.esc_timeout.store(micros as usize, Ordering::SeqCst);
+                self.adaptive_esc.store(false, Ordering::SeqCst);
+            }
+            EscTimeout::Adaptive => {
+                self.adaptive_esc.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub(crate) fn set_esc_policy(&self, policy: EscPolicy) {
+        let without_alt = policy == EscPolicy::Esc;
+        self.esc_without_alt.store(without_alt, Ordering::SeqCst);
+    }
+
+    // Whether the terminal has been resized since the last call.
+    pub(crate) fn take_resized(&self) -> bool {
+        self.resized.swap(false, Ordering::SeqCst)
+    }
+
+    // In inline mode (when `rows` is not zero), mouse coordinates are
+    // made relative to the rows, and events outside them (or before
+    // their place on the screen is known) are dropped.
+    pub(crate) fn set_inline_rows(&self, origin: Option<u16>, rows: u16) {
+        let origin = origin.map_or(NO_ORIGIN, usize::from);
+        self.inline_origin.store(origin, Ordering::SeqCst);
+        self.inline_rows.store(usize::from(rows), Ordering::SeqCst);
+    }
+
+    // The position in the inline rows of the screen position `coords`,
+    // if they contain it (or `coords` itself, if not in inline mode).
+    fn inline_coords(&self, coords: Coords) -> Option<Coords> {
+        let rows = self.inline_rows.load(Ordering::SeqCst);
+        if rows == 0 {
+            return Some(coords);
+        }
+        let origin = self.inline_origin.load(Ordering::SeqCst);
+        let row = usize::from(coords.1);
+        if origin == NO_ORIGIN || row < origin || row - origin >= rows {
+            return None;
+        }
+        Some((coords.0, (row - origin) as u16))
+    }
+
+    // The size in pixels of a character cell, if the terminal reports
+    // it.
+    pub(crate) fn cell_size(&self) -> Option<(u16, u16)> {
+        let win: libc::winsize = unsafe { mem::zeroed() };
+        let res = unsafe { libc::ioctl(self.fd, libc::TIOCGWINSZ, &win) };
+        if res != 0 || win.ws_col == 0 || win.ws_row == 0 {
+            return None;
+        }
+        let size = (win.ws_xpixel / win.ws_col, win.ws_ypixel / win.ws_row);
+        if size.0 == 0 || size.1 == 0 {
+            return None;
+        }
+        Some(size)
+    }
+
+    // Whether the terminal has job control.
+    pub(crate) fn is_controlling(&self) -> bool {
+        self.controlling
+    }
+
+    // Whether the process is in the terminal's foreground process
+    // group (or the input is not a terminal, or not the process's
+    // own); if not, reading from or changing the terminal would stop
+    // the process with SIGTTIN or SIGTTOU.
+    pub(crate) fn is_foreground(&self) -> bool {
+        if !self.controlling {
+            return true;
+        }
+        unsafe {
+            let pgrp = libc::tcgetpgrp(self.fd);
+            pgrp == -1 || pgrp == libc::getpgrp()
+        }
+    }
+
+    // Stops the process as if by the default action of SIGTSTP, and
+    // returns once it has been continued in the foreground.
+    pub(crate) fn stop_process(&self) {
+        unsafe {
+            let mut sa: libc::sigaction = mem::zeroed();
+            let mut old: libc::sigaction = mem::zeroed();
+            sa.sa_sigaction = libc::SIG_DFL;
+            libc::sigaction(libc::SIGTSTP, &sa, &mut old);
+            libc::raise(libc::SIGTSTP);
+            libc::sigaction(libc::SIGTSTP, &old, ptr::null_mut());
+            // If continued in the background, wait to be brought back.
+            while !self.is_foreground() {
+                libc::raise(libc::SIGTTOU);
+            }
+        }
     }
 }
 
+fn micros(duration: Duration) -> libc::suseconds_t {
+    let secs = cmp::min(duration.as_secs(), 1_000_000);
+    (secs * 1_000_000 + u64::from(duration.subsec_nanos() / 1000))
+        as libc::suseconds_t
+}
+
 const READ_BUF_SIZE: usize = 1024;
 const WAIT_MICROS: libc::suseconds_t = 10_000;
+const MAX_ADAPTIVE_MICROS: libc::suseconds_t = 500_000;
 
-// Convert input from stdin (and the signal pipe) into InputEvents.
-unsafe fn raw_event_loop(tx: Sender<Box<Event>>) {
+// Convert input from the terminal (and the signal pipe) into
+// InputEvents.
+fn raw_event_loop(mut reader: Reader, signals: bool, stop_fd: c_int) {
     // TODO: indicate errors?
-    let mut reader = Reader::new(Desc::current(), tx);
-    let signal_fd = SIGNAL_FDS.unwrap().0;
-    let mut stdin_buf = [0u8; READ_BUF_SIZE];
-    let mut override_timeout = false;
-    let mut timeout = libc::timeval {
-        tv_sec: 0,
-        tv_usec: 0,
-    };
-    loop {
-        let mut read_fds: libc::fd_set = mem::uninitialized();
-        let nfds: c_int = signal_fd + 1;
-        libc::FD_ZERO(&mut read_fds);
-        libc::FD_SET(0, &mut read_fds);
-        libc::FD_SET(signal_fd, &mut read_fds);
-        let timeout_ptr = if timeout.tv_usec == 0 {
-            ptr::null_mut()
+    let mut events = EventLoop::new(signals);
+    events.stop_fd = Some(stop_fd);
+    while let Ok(true) = events.step(&mut reader, None) {}
+}
+
+// Reads input from the terminal (and the signal pipe, if `signals` is
+// set) and parses it, either on the event loop thread or on the
+// calling thread of `poll_event`.
+pub(crate) struct EventLoop {
+    signals: bool,
+    // The event loop ends when this becomes readable.
+    stop_fd: Option<c_int>,
+    buf: [u8; READ_BUF_SIZE],
+    // When to stop waiting for the rest of a sequence.
+    esc_deadline: Option<Instant>,
+}
+
+impl EventLoop {
+    pub(crate) fn new(signals: bool) -> EventLoop {
+        EventLoop {
+            signals,
+            stop_fd: None,
+            buf: [0u8; READ_BUF_SIZE],
+            esc_deadline: None,
+        }
+    }
+
+    // Waits for input or a signal, or until `deadline` (if any), and
+    // parses whatever arrives. Returns false once there will be no
+    // more input.
+    pub(crate) fn step(
+        &mut self,
+        reader: &mut Reader,
+        deadline: Option<Instant>,
+    ) -> Result<bool> {
+        let shared = reader.shared.clone();
+        let fd = shared.fd;
+        let signal_fd = if self.signals {
+            unsafe { SIGNAL_FDS.unwrap().0 }
         } else {
-            override_timeout = false;
-            &mut timeout
+            fd
+        };
+        let mut read_fds: libc::fd_set = unsafe { mem::zeroed() };
+        // In the background, wait for SIGCONT instead of input.
+        let foreground = shared.is_foreground();
+        unsafe {
+            libc::FD_ZERO(&mut read_fds);
+            if foreground {
+                libc::FD_SET(fd, &mut read_fds);
+            }
+            if self.signals {
+                libc::FD_SET(signal_fd, &mut read_fds);
+            }
+            if let Some(stop_fd) = self.stop_fd {
+                libc::FD_SET(stop_fd, &mut read_fds);
+            }
+        }
+        let stop_fd = self.stop_fd.unwrap_or(0);
+        let nfds = cmp::max(cmp::max(signal_fd, fd), stop_fd);
+        let wake = match (self.esc_deadline, deadline) {
+            (Some(esc), Some(deadline)) => Some(cmp::min(esc, deadline)),
+            (esc, deadline) => esc.or(deadline),
+        };
+        let mut timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+        let timeout_ptr = match wake {
+            Some(wake) => {
+                let now = Instant::now();
+                if wake > now {
+                    // Rounded up, so as not to wake just before.
+                    let wait = wake - now;
+                    let micros = wait.as_secs() * 1_000_000
+                        + u64::from((wait.subsec_nanos() + 999) / 1000);
+                    timeout.tv_sec = (micros / 1_000_000) as libc::time_t;
+                    timeout.tv_usec =
+                        (micros % 1_000_000) as libc::suseconds_t;
+                }
+                &mut timeout as *mut _
+            }
+            None => ptr::null_mut(),
+        };
+        let select = unsafe {
+            libc::select(
+                nfds + 1,
+                &mut read_fds,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                timeout_ptr,
+            )
         };
-        let tstart = Instant::now();
-        let select = libc::select(
-            nfds + 1,
-            &mut read_fds,
-            ptr::null_mut(),
-            ptr::null_mut(),
-            timeout_ptr,
-        );
         match select {
-            -1 => return,
+            -1 => {
+                // A signal handled on this thread interrupts the
+                // select; the signal pipe is read next time.
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(true);
+                }
+                return Error::ffi_err("select failed");
+            }
             0 => {
-                timeout.tv_usec = 0;
-                if !override_timeout && reader.reset().is_err() {
-                    return;
+                let expired = self.esc_deadline
+                    .map_or(false, |esc| esc <= Instant::now());
+                if expired {
+                    self.esc_deadline = None;
+                    if reader.expire().is_err() {
+                        return Ok(false);
+                    }
                 }
+                return Ok(true);
             }
             _ => (),
         }
-        if libc::FD_ISSET(0, &mut read_fds) {
-            let bufptr = stdin_buf.as_mut_ptr() as *mut libc::c_void;
-            let len = libc::read(0, bufptr, READ_BUF_SIZE);
+        if let Some(stop_fd) = self.stop_fd {
+            if unsafe { libc::FD_ISSET(stop_fd, &mut read_fds) } {
+                return Ok(false);
+            }
+        }
+        if foreground && unsafe { libc::FD_ISSET(fd, &mut read_fds) } {
+            let bufptr = self.buf.as_mut_ptr() as *mut libc::c_void;
+            let len = unsafe { libc::read(fd, bufptr, READ_BUF_SIZE) };
+            if len < 0 && !shared.is_foreground() {
+                // Moved to the background since the select.
+                return Ok(true);
+            }
             if len < 1 {
-                return;
+                return Ok(false);
             }
-            match reader.parse_stdin(&stdin_buf[0..len as usize]) {
-                Err(_) => return,
-                Ok(ParseOk::Continue) => {
-                    override_timeout = true;
-                    timeout.tv_usec = 0;
-                }
+            match reader.parse_stdin(&self.buf[0..len as usize]) {
+                Err(_) => return Ok(false),
+                Ok(ParseOk::Continue) => self.esc_deadline = None,
                 Ok(ParseOk::Wait) => {
-                    timeout.tv_usec = WAIT_MICROS;
+                    let wait = reader.wait_micros() as u32 * 1000;
+                    self.esc_deadline =
+                        Some(Instant::now() + Duration::new(0, wait));
                 }
             }
         }
-        if libc::FD_ISSET(signal_fd, &mut read_fds) {
+        if self.signals && unsafe { libc::FD_ISSET(signal_fd, &mut read_fds) }
+        {
             let mut buf = 0u8;
             let bufptr = &mut buf as *mut _ as *mut libc::c_void;
-            let len = libc::read(signal_fd, bufptr, 1);
+            let len = unsafe { libc::read(signal_fd, bufptr, 1) };
             if len < 1 || !reader.parse_signal(buf).is_ok() {
-                return;
-            }
-            // Just in case we're here handling a signal in the middle
-            // of waiting for partial input to be complete (and we're
-            // on an OS where select doesn't decrement the timeout).
-            if timeout.tv_usec != 0 {
-                let elapsed = i64::from(tstart.elapsed().subsec_nanos() / 1000);
-                if elapsed < WAIT_MICROS {
-                    timeout.tv_usec = WAIT_MICROS - elapsed;
-                }
+                return Ok(false);
             }
         }
+        Ok(true)
+    }
+}
+
+// Reads and parses whatever input arrives within `timeout`, on the
+// calling thread; used to wait for query replies before the event
+// loop thread is started.
+pub(crate) fn read_inline(
+    reader: &mut Reader,
+    timeout: Duration,
+) -> Result<()> {
+    let mut stdin_buf = [0u8; READ_BUF_SIZE];
+    let fd = reader.shared.fd;
+    unsafe {
+        let mut read_fds: libc::fd_set = mem::uninitialized();
+        libc::FD_ZERO(&mut read_fds);
+        libc::FD_SET(fd, &mut read_fds);
+        let mut timeout = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: (timeout.subsec_nanos() / 1000) as libc::suseconds_t,
+        };
+        let select = libc::select(
+            fd + 1,
+            &mut read_fds,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut timeout,
+        );
+        match select {
+            -1 => return Error::ffi_err("select failed"),
+            0 => return Ok(()),
+            _ => (),
+        }
+        let bufptr = stdin_buf.as_mut_ptr() as *mut libc::c_void;
+        let len = libc::read(fd, bufptr, READ_BUF_SIZE);
+        if len < 1 {
+            return Error::ffi_err("read failed");
+        }
+        // Input events may have nowhere to go yet.
+        let _ = reader.parse_stdin(&stdin_buf[0..len as usize]);
     }
+    Ok(())
 }
 
 #[derive(Eq, PartialEq)]
@@ -172,46 +557,146 @@ enum ParseState {
     Esc1,
     Esc2,
     Mouse(escmouse::Type),
+    // Inside an OSC sequence; true if the last byte was ESC.
+    Osc(bool),
+    // Inside a CSI sequence that may be a reply or an extended key
+    // report.
+    Csi,
+    // Inside a bracketed paste.
+    Paste,
 }
 
-struct Reader {
+// The longest OSC sequence that will be parsed as a reply.
+const MAX_OSC_LEN: usize = 256;
+
+// The longest CSI sequence that will be parsed as a reply or an
+// extended key report.
+const MAX_CSI_LEN: usize = 64;
+
+// Longer pastes are sent as several events.
+const MAX_PASTE_LEN: usize = 0x1_0000;
+
+pub(crate) struct Reader {
     kparse: esckey::Parser,
     mparse: escmouse::Parser,
     utf8: Utf8Parser,
     tx: Sender<Box<Event>>,
+    replies: Option<Sender<Reply>>,
     hold_keys: Vec<Utf8Val>,
+    paste: Vec<u8>,
+    paste_end: Vec<u8>,
     state: ParseState,
     rxvt: bool,
     bs: u8,
     cbs: u8,
+    // The adaptive escape timeout, and when input last had to wait
+    // for the rest of a sequence, or timed out after a lone escape.
+    esc_wait: libc::suseconds_t,
+    waited: Option<Instant>,
+    timed_out: Option<Instant>,
+    // The first byte of a UTF-8 mouse coordinate, if the second has
+    // not yet been read.
+    mouse_lead: Option<u8>,
+    shared: Arc<Shared>,
 }
 
 impl Reader {
-    fn new(desc: &Desc, tx: Sender<Box<Event>>) -> Reader {
+    pub(crate) fn new(
+        desc: &Desc,
+        tx: Sender<Box<Event>>,
+        replies: Option<Sender<Reply>>,
+        shared: Arc<Shared>,
+    ) -> Reader {
         let (bs, cbs) = if desc[cap::kbs] == [0x7f] {
             (0x7f, 0x08)
         } else {
             (0x08, 0x7f)
         };
+        let pe = desc.get_str_ext(&UserDef::named("PE"));
+        let paste_end = if pe.is_empty() {
+            b"\x1b[201~".to_vec()
+        } else {
+            pe.to_vec()
+        };
         Reader {
             kparse: esckey::Parser::new(desc),
             mparse: escmouse::Parser::new(),
             utf8: Default::default(),
             tx,
+            replies,
             hold_keys: Vec::with_capacity(25),
+            paste: Vec::new(),
+            paste_end,
             state: ParseState::Init,
             rxvt: is_rxvt(desc),
             bs,
             cbs,
+            esc_wait: WAIT_MICROS,
+            waited: None,
+            timed_out: None,
+            mouse_lead: None,
+            shared,
+        }
+    }
+
+    // How long to wait for the rest of a sequence.
+    fn wait_micros(&self) -> libc::suseconds_t {
+        if self.shared.adaptive_esc.load(Ordering::SeqCst) {
+            return self.esc_wait;
+        }
+        match self.shared.esc_timeout.load(Ordering::SeqCst) {
+            0 => WAIT_MICROS,
+            micros => micros as libc::suseconds_t,
+        }
+    }
+
+    // Called when the rest of a sequence didn't arrive in time.
+    fn expire(&mut self) -> ParseResult {
+        self.waited = None;
+        if self.state == ParseState::Esc1 && self.hold_keys.is_empty() {
+            self.timed_out = Some(Instant::now());
+        }
+        self.reset()
+    }
+
+    // Updates the adaptive timeout, when the rest of a sequence
+    // arrives in time or seems to have arrived too late.
+    fn adapt(&mut self, data: &[u8]) {
+        let now = Instant::now();
+        if let Some(waited) = self.waited.take() {
+            let gap = micros(now - waited);
+            // A moving average of twice the gap, leaving a margin
+            // over how long sequences take to arrive.
+            let wait = (self.esc_wait * 7 + gap * 2) / 8;
+            self.esc_wait = cmp::max(WAIT_MICROS, wait);
+        }
+        if let Some(timed_out) = self.timed_out.take() {
+            let gap = micros(now - timed_out) + self.esc_wait;
+            let first = data.first().cloned().unwrap_or(0);
+            if gap < MAX_ADAPTIVE_MICROS && (first == b'[' || first == b'O') {
+                self.esc_wait = cmp::min(MAX_ADAPTIVE_MICROS, gap * 2);
+            }
         }
     }
 
     fn parse_signal(&self, data: u8) -> ParseResult {
+        let job_control = self.shared.job_control.load(Ordering::SeqCst);
+        if data == 1 || data == 6 {
+            self.shared.resized.store(true, Ordering::SeqCst);
+        }
         let event = match data {
-            1 => InputEvent::Repaint,
+            1 | 6 => InputEvent::Repaint,
             2 => return Err(()), // break out of loop for SIGTERM
             3 => InputEvent::Interrupt,
             4 => InputEvent::Break,
+            5 if job_control => InputEvent::Suspend,
+            5 => {
+                // Stop anyway, as if SIGTSTP had not been caught.
+                unsafe {
+                    libc::kill(libc::getpid(), libc::SIGSTOP);
+                }
+                return Ok(ParseOk::Continue);
+            }
             _ => unreachable!(),
         };
         self.send(event)
@@ -230,9 +715,29 @@ impl Reader {
     }
 
     fn send_mouse(&mut self, event: InputEvent) -> ParseResult {
+        let sgr = self.state == ParseState::Mouse(escmouse::Type::SGR);
         self.hold_keys.clear();
         self.state = ParseState::Init;
-        self.send(event)
+        let mut coords = match escmouse::coords(&event) {
+            Some(coords) => coords,
+            None => return self.send(event),
+        };
+        let mut pixels = None;
+        if sgr && self.shared.pixel_mouse.load(Ordering::SeqCst) {
+            // Without the size of a cell, the cell is unknown.
+            let cell = match self.shared.cell_size() {
+                Some(cell) => cell,
+                None => return Ok(ParseOk::Continue),
+            };
+            pixels = Some(coords);
+            coords = (coords.0 / cell.0, coords.1 / cell.1);
+        }
+        match self.shared.inline_coords(coords) {
+            Some(coords) => {
+                self.send(escmouse::with_coords(&event, coords, pixels))
+            }
+            None => Ok(ParseOk::Continue),
+        }
     }
 
     fn reset(&mut self) -> ParseResult {
@@ -243,6 +748,7 @@ impl Reader {
         match self.state {
             Init => (),
             Mouse(ty) => {
+                self.mouse_lead = None;
                 // These input bytes were discarded when parsing
                 // switched from "key mode" to "mouse mode", so we
                 // manually recreate them.
@@ -271,6 +777,22 @@ impl Reader {
                 self.hold_keys.insert(0, ([27, 0, 0, 0], 1, '\x1b'));
                 self.reset_with_alt()?;
             }
+            // An incomplete OSC sequence was probably typed, so it is
+            // sent as keys.
+            Osc(esc) => {
+                self.hold_keys.insert(0, Reader::ascii_cp(b']'));
+                if esc {
+                    self.hold_keys.push(Reader::ascii_cp(27));
+                }
+                self.reset_with_alt()?;
+            }
+            Csi => {
+                self.reset_with_alt()?;
+            }
+            Paste => {
+                let len = self.paste.len();
+                self.send_paste(len)?;
+            }
         };
         self.kparse.reset();
         self.hold_keys.clear();
@@ -280,6 +802,14 @@ impl Reader {
 
     fn reset_with_alt(&self) -> ParseResult {
         use input::Mods;
+        if self.shared.esc_without_alt.load(Ordering::SeqCst) {
+            self.send_key(Key::Esc, Mods::empty())?;
+            for cp in &self.hold_keys {
+                let (key, mods) = self.xlate_cp(*cp);
+                self.send_key(key, mods)?;
+            }
+            return Ok(ParseOk::Continue);
+        }
         let (key, mods) = self.xlate_cp(self.hold_keys[0]);
         self.send_key(key, mods | Mods::ALT)?;
         for cp in &self.hold_keys[1..] {
@@ -290,6 +820,15 @@ impl Reader {
     }
 
     fn parse_stdin(&mut self, data: &[u8]) -> ParseResult {
+        self.adapt(data);
+        let res = self.parse_bytes(data);
+        if let Ok(ParseOk::Wait) = res {
+            self.waited = Some(Instant::now());
+        }
+        res
+    }
+
+    fn parse_bytes(&mut self, data: &[u8]) -> ParseResult {
         use self::ParseState::*;
         use self::escmouse::Type::*;
 
@@ -297,11 +836,38 @@ impl Reader {
         while pos < data.len() {
             // Mouse coordinates in normal mode can have (single-byte)
             // values between 128 and 255, so pass them along without
-            // parsing as UTF-8.
+            // parsing as UTF-8, unless UTF-8 extended mode is in
+            // effect, when they may be two-byte characters (which can
+            // be split between reads).
             if self.state == Mouse(Normal) {
-                let cp = ([data[pos], 0, 0, 0], 1, '\x00');
+                let byte = data[pos];
+                if let Some(lead) = self.mouse_lead.take() {
+                    if byte & 0xc0 == 0x80 {
+                        let val = u16::from(lead & 0x1f) << 6
+                            | u16::from(byte & 0x3f);
+                        let c = ::std::char::from_u32(u32::from(val)).unwrap();
+                        pos += 1;
+                        self.parse_mouse(([lead, byte, 0, 0], 2, c), val)?;
+                        continue;
+                    }
+                    // Not UTF-8 after all.
+                    self.parse_cp(([lead, 0, 0, 0], 1, '\x00'))?;
+                    continue;
+                }
+                pos += 1;
+                let utf8 = self.shared.utf8_mouse.load(Ordering::SeqCst);
+                if utf8 && byte >= 0xc2 && byte < 0xe0 {
+                    self.mouse_lead = Some(byte);
+                    continue;
+                }
+                self.parse_cp(([byte, 0, 0, 0], 1, '\x00'))?;
+                continue;
+            }
+            // Pasted text is collected as bytes, and only converted
+            // (lossily) to UTF-8 when sent.
+            if self.state == Paste {
+                self.parse_paste(data[pos])?;
                 pos += 1;
-                self.parse_cp(cp)?;
                 continue;
             }
             let read = self.utf8.read(&data[pos..]);
@@ -317,7 +883,8 @@ impl Reader {
                 }
             }
         }
-        if self.state != Init {
+        // Pastes are not timed out, since they can arrive slowly.
+        if self.state != Init && self.state != Paste {
             return Ok(ParseOk::Wait);
         }
         Ok(ParseOk::Continue)
@@ -327,7 +894,19 @@ impl Reader {
     fn parse_cp(&mut self, cp: Utf8Val) -> ParseResult {
         use self::ParseState::*;
 
+        if let Osc(esc) = self.state {
+            return self.parse_osc(cp, esc);
+        }
+
+        if self.state == Csi {
+            return self.parse_csi(cp);
+        }
+
         if self.state == Init {
+            let job_control = self.shared.job_control.load(Ordering::SeqCst);
+            if cp.0[0] == 0x1a && job_control {
+                return self.send(InputEvent::Suspend);
+            }
             let (key, mods) = self.xlate_cp(cp);
             if key == Key::Esc {
                 self.reset()?;
@@ -358,12 +937,24 @@ impl Reader {
         }
 
         if let Mouse(_) = self.state {
+            return self.parse_mouse(cp, u16::from(cp.0[0]));
+        }
+
+        // Terminals reply to some queries with OSC sequences.
+        if self.state == Esc1 && self.hold_keys.is_empty() && cp.0[0] == b']'
+        {
+            self.state = Osc(false);
+            return Ok(ParseOk::Continue);
+        }
+
+        if self.state == Esc1 && self.hold_keys.is_empty() && cp.0[0] == b'['
+            && (self.shared.cpr_pending.load(Ordering::SeqCst)
+                || self.shared.mode_pending.load(Ordering::SeqCst)
+                || self.shared.extended_keys.load(Ordering::SeqCst))
+        {
             self.hold_keys.push(cp);
-            return match self.mparse.parse(cp.0[0]) {
-                escmouse::ParseResult::No => self.reset(),
-                escmouse::ParseResult::Maybe => Ok(ParseOk::Continue),
-                escmouse::ParseResult::Found(evt) => self.send_mouse(evt),
-            };
+            self.state = Csi;
+            return Ok(ParseOk::Continue);
         }
 
         // Handle subsequent bytes of key sequences.
@@ -375,6 +966,139 @@ impl Reader {
         self.search_key_seq(cp)
     }
 
+    // Parse one codepoint of an OSC sequence, which ends with BEL or
+    // ST (ESC \).
+    fn parse_osc(&mut self, cp: Utf8Val, esc: bool) -> ParseResult {
+        if esc {
+            if cp.0[0] != b'\\' {
+                // Not a reply after all.
+                self.state = ParseState::Osc(false);
+                self.reset()?;
+                self.state = ParseState::Esc1;
+                return self.parse_cp(cp);
+            }
+        } else if cp.0[0] == 27 {
+            self.state = ParseState::Osc(true);
+            return Ok(ParseOk::Continue);
+        } else if cp.0[0] != 7 {
+            self.hold_keys.push(cp);
+            if self.hold_keys.len() > MAX_OSC_LEN {
+                return self.reset();
+            }
+            return Ok(ParseOk::Continue);
+        }
+        let bytes: Vec<u8> = self.hold_keys
+            .iter()
+            .flat_map(|cp| cp.0[0..cp.1].iter().cloned())
+            .collect();
+        self.hold_keys.clear();
+        self.state = ParseState::Init;
+        if let Some(ref replies) = self.replies {
+            if let Some(reply) = escreply::parse_osc(&bytes) {
+                // Nobody may be waiting for the reply any more.
+                let _ = replies.send(reply);
+            }
+        }
+        Ok(ParseOk::Continue)
+    }
+
+    // Parse one codepoint of a CSI sequence that may be a reply or an
+    // extended key report. If it turns out to be neither, the held
+    // bytes are parsed again as a key sequence.
+    fn parse_csi(&mut self, cp: Utf8Val) -> ParseResult {
+        use self::esccsi::Decoded;
+
+        let b = cp.0[0];
+        if cp.1 == 1 && b >= 0x20 && b < 0x40
+            && self.hold_keys.len() < MAX_CSI_LEN
+        {
+            self.hold_keys.push(cp);
+            return Ok(ParseOk::Continue);
+        }
+        if cp.1 == 1 && b >= 0x40 && b < 0x7f {
+            let params: Vec<u8> =
+                self.hold_keys[1..].iter().map(|cp| cp.0[0]).collect();
+            let cpr = self.shared.cpr_pending.load(Ordering::SeqCst);
+            if let Some(reply) = escreply::parse_csi(&params, b, cpr) {
+                self.hold_keys.clear();
+                self.state = ParseState::Init;
+                if let Some(ref replies) = self.replies {
+                    let _ = replies.send(reply);
+                }
+                return Ok(ParseOk::Continue);
+            }
+            if self.shared.extended_keys.load(Ordering::SeqCst) {
+                match esccsi::parse_key(&params, b) {
+                    Some(Decoded::Event(evt)) => {
+                        self.hold_keys.clear();
+                        self.state = ParseState::Init;
+                        return self.send(evt);
+                    }
+                    Some(Decoded::Ignore) => {
+                        self.hold_keys.clear();
+                        self.state = ParseState::Init;
+                        return Ok(ParseOk::Continue);
+                    }
+                    None => (),
+                }
+            }
+        }
+        let held = ::std::mem::replace(&mut self.hold_keys, Vec::new());
+        self.state = ParseState::Esc1;
+        self.hold_keys.push(held[0]);
+        self.search_key_seq(held[0])?;
+        for &cp in held[1..].iter().chain(Some(cp).iter()) {
+            self.parse_cp(cp)?;
+        }
+        Ok(ParseOk::Continue)
+    }
+
+    // Collect one byte of a bracketed paste, which ends with PE.
+    fn parse_paste(&mut self, byte: u8) -> ParseResult {
+        self.paste.push(byte);
+        if self.paste.ends_with(&self.paste_end) {
+            let len = self.paste.len() - self.paste_end.len();
+            self.state = ParseState::Init;
+            self.send_paste(len)?;
+            self.paste.clear();
+            return Ok(ParseOk::Continue);
+        }
+        if self.paste.len() < MAX_PASTE_LEN {
+            return Ok(ParseOk::Continue);
+        }
+        // Keep what may be the start of PE, and don't split a
+        // character.
+        let end = self.paste.len() + 1 - self.paste_end.len();
+        let mut len = end;
+        while len > 0 && self.paste[len] & 0xc0 == 0x80 {
+            len -= 1;
+        }
+        if len == 0 {
+            len = end;
+        }
+        self.send_paste(len)
+    }
+
+    fn send_paste(&mut self, len: usize) -> ParseResult {
+        let text = String::from_utf8_lossy(&self.paste[..len]).into_owned();
+        self.paste.drain(..len);
+        self.send(InputEvent::Paste(text))
+    }
+
+    fn ascii_cp(byte: u8) -> Utf8Val {
+        ([byte, 0, 0, 0], 1, byte as char)
+    }
+
+    // Parse one value of a mouse sequence.
+    fn parse_mouse(&mut self, cp: Utf8Val, val: u16) -> ParseResult {
+        self.hold_keys.push(cp);
+        match self.mparse.parse(val) {
+            escmouse::ParseResult::No => self.reset(),
+            escmouse::ParseResult::Maybe => Ok(ParseOk::Continue),
+            escmouse::ParseResult::Found(evt) => self.send_mouse(evt),
+        }
+    }
+
     fn search_key_seq(&mut self, cp: Utf8Val) -> ParseResult {
         use self::esckey::ParseResult::*;
         use input::Mods;
@@ -392,7 +1116,7 @@ impl Reader {
                     self.mparse.reset(escmouse::Type::Urxvt);
                     for i in 1..self.hold_keys.len() {
                         let cp = self.hold_keys[i];
-                        match self.mparse.parse(cp.0[0]) {
+                        match self.mparse.parse(u16::from(cp.0[0])) {
                             Mouse::No => return self.reset(),
                             Mouse::Maybe => continue,
                             Mouse::Found(evt) => return self.send_mouse(evt),
@@ -407,14 +1131,29 @@ impl Reader {
             }
             Maybe => Ok(ParseOk::Continue),
             Found((k, m)) => {
-                let m = match self.state {
-                    ParseState::Esc2 => m | Mods::ALT,
-                    _ => m,
+                let esc2 = self.state == ParseState::Esc2;
+                let m = if !esc2 {
+                    m
+                } else if self.shared.esc_without_alt.load(Ordering::SeqCst) {
+                    self.send_key(Key::Esc, Mods::empty())?;
+                    m
+                } else {
+                    m | Mods::ALT
                 };
                 self.hold_keys.clear();
                 self.state = ParseState::Init;
                 self.send_key(k, m)
             }
+            Paste => {
+                self.hold_keys.clear();
+                self.state = ParseState::Paste;
+                Ok(ParseOk::Continue)
+            }
+            Focus(focused) => {
+                self.hold_keys.clear();
+                self.state = ParseState::Init;
+                self.send(InputEvent::Focus(focused))
+            }
             Mouse(mtype) => {
                 self.mparse.reset(mtype);
                 self.hold_keys.clear();
@@ -585,8 +1324,9 @@ impl Utf8Parser {
 #[allow(unused_must_use)]
 mod test {
     use std::sync::mpsc::{channel, Receiver};
+    use libc;
     use tinf::Desc;
-    use super::{Event, InputEvent, Key, Mods, Reader};
+    use super::{Event, InputEvent, Key, Mods, Reader, Reply};
 
     fn desc() -> Desc {
         use tinf::cap::*;
@@ -606,7 +1346,29 @@ mod test {
     fn extract_event(rx: &Receiver<Box<Event>>) -> InputEvent {
         let evt = rx.recv().unwrap();
         let in_evt = evt.as_any().downcast_ref::<InputEvent>().unwrap();
-        *in_evt
+        in_evt.clone()
+    }
+
+    // A reader of a new pipe, its events, and the pipe's read and
+    // write descriptors.
+    fn pipe_reader(
+        desc: &Desc,
+    ) -> (Reader, Receiver<Box<Event>>, [libc::c_int; 2]) {
+        use std::sync::Arc;
+        use super::Shared;
+
+        let mut fds = [0; 2];
+        assert_eq!(0, unsafe { libc::pipe(fds.as_mut_ptr()) });
+        let (tx, rx) = channel();
+        let shared = Arc::new(Shared::new(fds[0], false));
+        (Reader::new(desc, tx, None, shared), rx, fds)
+    }
+
+    fn write_fd(fd: libc::c_int, bytes: &[u8]) {
+        let ptr = bytes.as_ptr() as *const libc::c_void;
+        assert_eq!(bytes.len() as isize, unsafe {
+            libc::write(fd, ptr, bytes.len())
+        });
     }
 
     #[test]
@@ -615,7 +1377,7 @@ mod test {
 
         let (tx, rx) = channel();
         let desc = desc();
-        let mut rdr = Reader::new(&desc, tx);
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
         rdr.parse_stdin(b"\x1b");
         rdr.reset();
         assert_eq!(expected, extract_event(&rx));
@@ -628,7 +1390,7 @@ mod test {
 
         let (tx, rx) = channel();
         let desc = desc();
-        let mut rdr = Reader::new(&desc, tx);
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
         rdr.parse_stdin(b"\x1b\x1b");
         rdr.reset();
         assert_eq!(expected, extract_event(&rx));
@@ -646,7 +1408,7 @@ mod test {
 
         let (tx, rx) = channel();
         let desc = desc();
-        let mut rdr = Reader::new(&desc, tx);
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
         rdr.parse_stdin(b"\x1b1");
         rdr.reset();
         assert_eq!(expected, extract_event(&rx));
@@ -658,7 +1420,7 @@ mod test {
         let expected2 = InputEvent::Key(Key::F5, Mods::empty());
         let (tx, rx) = channel();
         let desc = desc();
-        let mut rdr = Reader::new(&desc, tx);
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
         rdr.parse_stdin(b"\x1b\x1b[15~");
         assert_eq!(expected1, extract_event(&rx));
         assert_eq!(expected2, extract_event(&rx));
@@ -671,8 +1433,420 @@ mod test {
 
         let (tx, rx) = channel();
         let desc = desc_rxvt();
-        let mut rdr = Reader::new(&desc, tx);
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
         rdr.parse_stdin(b"\x1b\x1b[15~");
         assert_eq!(expected, extract_event(&rx));
     }
+
+    #[test]
+    fn osc_reply() {
+        let (tx, rx) = channel();
+        let (reply_tx, reply_rx) = channel();
+        let desc = desc();
+        let shared = Default::default();
+        let mut rdr = Reader::new(&desc, tx, Some(reply_tx), shared);
+        rdr.parse_stdin(b"\x1b]11;rgb:0000/0000/ffff\x07a");
+        rdr.parse_stdin(b"\x1b]10;rgb:ff/ff/ff\x1b");
+        rdr.parse_stdin(b"\\");
+        assert_eq!(
+            Reply::Dynamic(1, (0, 0, 255)),
+            reply_rx.try_recv().unwrap()
+        );
+        assert_eq!(
+            Reply::Dynamic(0, (255, 255, 255)),
+            reply_rx.try_recv().unwrap()
+        );
+        let expected = InputEvent::Key(Key::ascii(b'a'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn alt_bracket() {
+        use input::Mods;
+
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        rdr.parse_stdin(b"\x1b]");
+        rdr.reset();
+        let expected = InputEvent::Key(Key::ascii(b']'), Mods::ALT);
+        assert_eq!(expected, extract_event(&rx));
+
+        rdr.parse_stdin(b"\x1b]x\x1b[15~");
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Key(Key::ascii(b'x'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Key(Key::F5, Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+    }
+
+    #[test]
+    fn job_control() {
+        use input::Mods;
+
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        rdr.parse_stdin(b"\x1a");
+        let expected = InputEvent::Key(Key::ascii(b'Z'), Mods::CTRL);
+        assert_eq!(expected, extract_event(&rx));
+
+        rdr.shared.set_job_control(true);
+        rdr.parse_stdin(b"\x1a");
+        rdr.shared.set_job_control(false);
+        assert_eq!(InputEvent::Suspend, extract_event(&rx));
+    }
+
+    fn cpr_desc() -> Desc {
+        use tinf::cap::*;
+        desc![
+            kf3 => b"\x1bOR",
+            kf5 => b"\x1b[15~",
+            kRIT => b"\x1b[1;2C",
+        ]
+    }
+
+    #[test]
+    fn cursor_report() {
+        use input::Mods;
+
+        let (tx, rx) = channel();
+        let (reply_tx, reply_rx) = channel();
+        let desc = cpr_desc();
+        let shared = Default::default();
+        let mut rdr = Reader::new(&desc, tx, Some(reply_tx), shared);
+        rdr.parse_stdin(b"\x1b[1;2R");
+        let expected = InputEvent::Key(Key::F3, Mods::SHIFT);
+        assert_eq!(expected, extract_event(&rx));
+
+        rdr.shared.expect_cursor_report(true);
+        rdr.parse_stdin(b"\x1b[1;2R\x1b[15~\x1b[24;");
+        rdr.parse_stdin(b"80R");
+        rdr.parse_stdin(b"\x1b[2x");
+        rdr.shared.expect_cursor_report(false);
+        assert_eq!(Reply::Cursor(1, 2), reply_rx.try_recv().unwrap());
+        assert_eq!(Reply::Cursor(24, 80), reply_rx.try_recv().unwrap());
+        let expected = InputEvent::Key(Key::F5, Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Key(Key::ascii(b'['), Mods::ALT);
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Key(Key::ascii(b'2'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Key(Key::ascii(b'x'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn paste() {
+        use input::Mods;
+
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        rdr.parse_stdin(b"\x1b[200~a\x1b[15~\xe2\x82");
+        rdr.parse_stdin(b"\xac\xff\x1b[20");
+        rdr.parse_stdin(b"1~b");
+        let expected = InputEvent::Paste("a\x1b[15~\u{20ac}\u{fffd}".into());
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Key(Key::ascii(b'b'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+
+        let mut long = b"\x1b[200~".to_vec();
+        long.extend(vec![b'x'; super::MAX_PASTE_LEN + 10]);
+        long.extend(b"\x1b[201~");
+        rdr.parse_stdin(&long);
+        match extract_event(&rx) {
+            InputEvent::Paste(text) => {
+                assert_eq!(super::MAX_PASTE_LEN - 5, text.len())
+            }
+            _ => panic!(),
+        }
+        let expected = InputEvent::Paste(String::from("x").repeat(15));
+        assert_eq!(expected, extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn focus() {
+        use input::Mods;
+
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        rdr.parse_stdin(b"\x1b[O\x1b[Ia\x1b[");
+        rdr.parse_stdin(b"O");
+        assert_eq!(InputEvent::Focus(false), extract_event(&rx));
+        assert_eq!(InputEvent::Focus(true), extract_event(&rx));
+        let expected = InputEvent::Key(Key::ascii(b'a'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        assert_eq!(InputEvent::Focus(false), extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn extended_keys() {
+        use input::Mods;
+
+        let (tx, rx) = channel();
+        let (reply_tx, reply_rx) = channel();
+        let desc = desc();
+        let shared = Default::default();
+        let mut rdr = Reader::new(&desc, tx, Some(reply_tx), shared);
+        rdr.shared.expect_extended_keys(true);
+        rdr.parse_stdin(b"\x1b[?1u\x1b[105;5u\x1b[27u\x1b[57441u");
+        rdr.parse_stdin(b"\x1b[15;1:3~\x1b[200~x\x1b[201~");
+        rdr.shared.expect_extended_keys(false);
+        assert_eq!(Reply::Keyboard(1), reply_rx.try_recv().unwrap());
+        let expected = InputEvent::Key(Key::ascii(b'i'), Mods::CTRL);
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Key(Key::Esc, Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::KeyRelease(Key::F5, Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Paste("x".into());
+        assert_eq!(expected, extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn mouse_buttons() {
+        use input::{ButtonMotion, MouseButton, WheelMotion};
+        use input::Mods;
+
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        rdr.shared.expect_utf8_mouse(true);
+        rdr.parse_stdin(b"\x1b[M !!\x1b[M@\xc5\x8c!\x1b[M#\xff\xff");
+        let expected = InputEvent::Mouse(
+            ButtonMotion::Press,
+            MouseButton::Left,
+            Mods::empty(),
+            (0, 0),
+            None,
+        );
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::MouseDrag(
+            MouseButton::Left,
+            Mods::empty(),
+            (299, 0),
+            None,
+        );
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Mouse(
+            ButtonMotion::Release,
+            MouseButton::Unknown,
+            Mods::empty(),
+            (222, 222),
+            None,
+        );
+        assert_eq!(expected, extract_event(&rx));
+
+        rdr.parse_stdin(b"\x1b[<66;5;3M\x1b[<128;1;1M\x1b[<129;1;1m");
+        rdr.parse_stdin(b"\x1b[<50;2;2M\x1b[<35;2;2M");
+        let expected = InputEvent::MouseWheel(
+            WheelMotion::Left,
+            Mods::empty(),
+            (4, 2),
+            None,
+        );
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Mouse(
+            ButtonMotion::Press,
+            MouseButton::Back,
+            Mods::empty(),
+            (0, 0),
+            None,
+        );
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::Mouse(
+            ButtonMotion::Release,
+            MouseButton::Forward,
+            Mods::empty(),
+            (0, 0),
+            None,
+        );
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::MouseDrag(
+            MouseButton::Right,
+            Mods::CTRL,
+            (1, 1),
+            None,
+        );
+        assert_eq!(expected, extract_event(&rx));
+        let expected = InputEvent::MouseMove(Mods::empty(), (1, 1), None);
+        assert_eq!(expected, extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn pixel_mouse() {
+        use std::sync::Arc;
+        use input::{ButtonMotion, MouseButton};
+        use input::Mods;
+
+        let press = |coords, pixels| {
+            let (b, m) = (MouseButton::Left, Mods::empty());
+            InputEvent::Mouse(ButtonMotion::Press, b, m, coords, pixels)
+        };
+        let (master, slave) = unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(0, libc::grantpt(master));
+            assert_eq!(0, libc::unlockpt(master));
+            let name = libc::ptsname(master);
+            (master, libc::open(name, libc::O_RDWR | libc::O_NOCTTY))
+        };
+        let (tx, rx) = channel();
+        let desc = desc();
+        let shared = Arc::new(super::Shared::new(slave, false));
+        let mut rdr = Reader::new(&desc, tx, None, shared);
+        rdr.shared.expect_pixel_mouse(true);
+        // Without the size of a cell, events are dropped.
+        rdr.parse_stdin(b"\x1b[<0;25;41M");
+        assert!(rx.try_recv().is_err());
+
+        let ws = libc::winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 800,
+            ws_ypixel: 480,
+        };
+        assert_eq!(0, unsafe { libc::ioctl(master, libc::TIOCSWINSZ, &ws) });
+        rdr.parse_stdin(b"\x1b[<0;25;41M");
+        assert_eq!(press((2, 2), Some((24, 40))), extract_event(&rx));
+        // Only SGR reports are in pixels.
+        rdr.parse_stdin(b"\x1b[M !!");
+        assert_eq!(press((0, 0), None), extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    fn inline_mouse() {
+        use input::{ButtonMotion, MouseButton};
+        use input::Mods;
+
+        let press = |coords| {
+            let (b, m) = (MouseButton::Left, Mods::empty());
+            InputEvent::Mouse(ButtonMotion::Press, b, m, coords, None)
+        };
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        // Until the rows are found, mouse events are dropped.
+        rdr.shared.set_inline_rows(None, 3);
+        rdr.parse_stdin(b"\x1b[<0;5;11M");
+        assert!(rx.try_recv().is_err());
+
+        rdr.shared.set_inline_rows(Some(10), 3);
+        rdr.parse_stdin(b"\x1b[<0;5;11M\x1b[<0;5;13M\x1b[<0;5;14M");
+        assert_eq!(press((4, 0)), extract_event(&rx));
+        assert_eq!(press((4, 2)), extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn utf8_mouse() {
+        use input::{ButtonMotion, MouseButton};
+        use input::Mods;
+
+        let press = |coords| {
+            let (b, m) = (MouseButton::Left, Mods::empty());
+            InputEvent::Mouse(ButtonMotion::Press, b, m, coords, None)
+        };
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        // Without mode 1005, bytes that look like UTF-8 are not.
+        rdr.parse_stdin(b"\x1b[M \xa2\x80");
+        assert_eq!(press((129, 95)), extract_event(&rx));
+
+        // A coordinate can be split between reads.
+        rdr.shared.expect_utf8_mouse(true);
+        rdr.parse_stdin(b"\x1b[M \xc4");
+        rdr.parse_stdin(b"\x80!");
+        assert_eq!(press((223, 0)), extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn adaptive_timeout() {
+        use super::WAIT_MICROS;
+
+        let (tx, rx) = channel();
+        let desc = desc();
+        let mut rdr = Reader::new(&desc, tx, None, Default::default());
+        rdr.parse_stdin(b"\x1b");
+        rdr.expire();
+        rdr.parse_stdin(b"[15~");
+        let expected = InputEvent::Key(Key::Esc, Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        while rx.try_recv().is_ok() {}
+        let wait = rdr.esc_wait;
+        assert!(wait >= 2 * WAIT_MICROS);
+
+        rdr.parse_stdin(b"\x1b[1");
+        rdr.parse_stdin(b"5~");
+        let expected = InputEvent::Key(Key::F5, Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        assert!(rdr.esc_wait < wait && rdr.esc_wait >= WAIT_MICROS);
+    }
+
+    #[test]
+    fn event_loop() {
+        use std::time::{Duration, Instant};
+        use super::EventLoop;
+
+        let desc = desc();
+        let (mut rdr, rx, fds) = pipe_reader(&desc);
+        let mut events = EventLoop::new(false);
+
+        write_fd(fds[1], b"a\x1b");
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert!(events.step(&mut rdr, Some(deadline)).unwrap());
+        let expected = InputEvent::Key(Key::ascii(b'a'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        assert!(rx.try_recv().is_err());
+        // The lone escape is sent once the escape timeout expires.
+        assert!(events.step(&mut rdr, Some(deadline)).unwrap());
+        let expected = InputEvent::Key(Key::Esc, Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(events.step(&mut rdr, Some(deadline)).unwrap());
+        assert!(rx.try_recv().is_err());
+        unsafe {
+            libc::close(fds[1]);
+        }
+        assert!(!events.step(&mut rdr, None).unwrap());
+        unsafe {
+            libc::close(fds[0]);
+        }
+    }
+
+    #[test]
+    fn input_thread_stops() {
+        use super::start_threads;
+
+        let desc = desc();
+        let (rdr, rx, fds) = pipe_reader(&desc);
+        let thread = start_threads(rdr, false).unwrap();
+        write_fd(fds[1], b"a");
+        let expected = InputEvent::Key(Key::ascii(b'a'), Mods::empty());
+        assert_eq!(expected, extract_event(&rx));
+        drop(thread);
+        // The thread has exited, dropping the reader's sender.
+        write_fd(fds[1], b"b");
+        assert!(rx.recv().is_err());
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
 }
@@ -1,13 +1,16 @@
 #![cfg(windows)]
 
 use std::{ptr, thread, time};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT,
+                        ATOMIC_USIZE_INIT};
 use std::sync::mpsc::{channel, Sender};
 use winapi::{self, KEY_EVENT_RECORD};
 use kernel32;
 use user32;
 use tvis_util::Handle;
-use input::{Event, InputEvent, Key, Mods};
-use {Error, Result};
+use input::{Event, InputEvent, Key, Mods, MouseButton};
+use term::MouseMode;
+use {Coords, Error, Result};
 
 const SHUTDOWN_KEY: u16 = 0x1111;
 const SIGINT_KEY: u16 = 0x2222;
@@ -17,6 +20,48 @@ const SIGQUIT_KEY: u16 = 0x3333;
 const EVENT_CONSOLE_LAYOUT: winapi::DWORD = 0x4005;
 const WINEVENT_OUTOFCONTEXT: winapi::DWORD = 0;
 const WINEVENT_SKIPOWNTHREAD: winapi::DWORD = 1;
+const QS_ALLINPUT: winapi::DWORD = 0x04ff;
+const PM_REMOVE: winapi::UINT = 1;
+
+// The console always reports mouse movement, so it is filtered here
+// according to the mouse mode.
+static IGNORE_MOVES: AtomicBool = ATOMIC_BOOL_INIT;
+static IGNORE_DRAGS: AtomicBool = ATOMIC_BOOL_INIT;
+
+// In inline mode, the number of rows (zero otherwise) and the buffer
+// row of the first, which mouse coordinates are made relative to.
+static INLINE_ROWS: AtomicUsize = ATOMIC_USIZE_INIT;
+static INLINE_ORIGIN: AtomicUsize = ATOMIC_USIZE_INIT;
+
+pub(crate) fn set_inline_rows(origin: u16, rows: u16) {
+    INLINE_ORIGIN.store(usize::from(origin), Ordering::SeqCst);
+    INLINE_ROWS.store(usize::from(rows), Ordering::SeqCst);
+}
+
+// The position in the inline rows of `coords`, if they contain it (or
+// `coords` itself, if not in inline mode).
+fn inline_coords(coords: Coords) -> Option<Coords> {
+    let rows = INLINE_ROWS.load(Ordering::SeqCst);
+    if rows == 0 {
+        return Some(coords);
+    }
+    let origin = INLINE_ORIGIN.load(Ordering::SeqCst);
+    let row = usize::from(coords.1);
+    if row < origin || row - origin >= rows {
+        return None;
+    }
+    Some((coords.0, (row - origin) as u16))
+}
+
+pub(crate) fn set_mouse_mode(mode: MouseMode) {
+    let (moves, drags) = match mode {
+        MouseMode::None | MouseMode::Press => (true, true),
+        MouseMode::Drag => (true, false),
+        MouseMode::Any => (false, false),
+    };
+    IGNORE_MOVES.store(moves, Ordering::SeqCst);
+    IGNORE_DRAGS.store(drags, Ordering::SeqCst);
+}
 
 pub(crate) fn start_threads(tx: Sender<Box<Event>>) -> Result<()> {
     register_ctrl_handler()?;
@@ -37,6 +82,17 @@ pub(crate) fn start_threads(tx: Sender<Box<Event>>) -> Result<()> {
     Ok(())
 }
 
+// Sets up reading input on the calling thread, for `poll_event`; the
+// thread must call `ConsoleReader::step` to receive window messages.
+pub(crate) fn start_polling(tx: Sender<Box<Event>>) -> Result<ConsoleReader> {
+    register_ctrl_handler()?;
+    unsafe {
+        create_session_wnd()?;
+    }
+    register_layout_hook()?;
+    ConsoleReader::new(tx)
+}
+
 fn register_ctrl_handler() -> Result<()> {
     extern "system" fn handler(ctrl_type: winapi::DWORD) -> winapi::BOOL {
         match ctrl_type {
@@ -197,6 +253,16 @@ unsafe fn run_message_pump() {
     }
 }
 
+// Dispatches the messages already waiting, without blocking.
+unsafe fn dispatch_messages() {
+    let mut msg: winapi::MSG = ::std::mem::uninitialized();
+    while 0 != user32::PeekMessageW(&mut msg, ptr::null_mut(), 0, 0, PM_REMOVE)
+    {
+        user32::TranslateMessage(&msg);
+        user32::DispatchMessageW(&msg);
+    }
+}
+
 fn write_fake_key(key_code: u16) -> winapi::BOOL {
     let mut key: winapi::INPUT_RECORD =
         unsafe { ::std::mem::uninitialized() };
@@ -219,20 +285,72 @@ fn raw_event_loop(tx: Sender<Box<Event>>) {
     let _ = event_loop(tx);
 }
 
-#[cfg_attr(feature = "cargo-clippy",
-           allow(needless_range_loop, needless_pass_by_value))]
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 fn event_loop(tx: Sender<Box<Event>>) -> Result<()> {
-    let mut resizer = Resizer::from_conout()?;
-    let in_hndl = Handle::Stdin.win_handle();
-    let mut buffer: [winapi::INPUT_RECORD; 128] =
-        unsafe { ::std::mem::uninitialized() };
-    let mut key_reader = KeyReader::new(tx.clone());
-    let mut mouse_reader = MouseReader::new(tx.clone());
-    loop {
+    let mut reader = ConsoleReader::new(tx)?;
+    while reader.read()? {}
+    Ok(())
+}
+
+// Reads console input, either on the input thread or on the calling
+// thread of `poll_event`.
+pub(crate) struct ConsoleReader {
+    in_hndl: winapi::HANDLE,
+    resizer: Resizer,
+    key_reader: KeyReader,
+    mouse_reader: MouseReader,
+    tx: Sender<Box<Event>>,
+}
+
+impl ConsoleReader {
+    fn new(tx: Sender<Box<Event>>) -> Result<ConsoleReader> {
+        Ok(ConsoleReader {
+            in_hndl: Handle::Stdin.win_handle(),
+            resizer: Resizer::from_conout()?,
+            key_reader: KeyReader::new(tx.clone()),
+            mouse_reader: MouseReader::new(tx.clone()),
+            tx,
+        })
+    }
+
+    // Waits up to `timeout` for input, dispatching window messages
+    // meanwhile, and reads it. Returns false once there will be no
+    // more input.
+    pub(crate) fn step(&mut self, timeout: time::Duration) -> Result<bool> {
+        let millis = timeout.as_secs() * 1000
+            + u64::from(timeout.subsec_nanos() / 1_000_000);
+        let millis = ::std::cmp::min(millis, u64::from(winapi::INFINITE - 1));
+        let res = unsafe {
+            user32::MsgWaitForMultipleObjects(
+                1,
+                &self.in_hndl,
+                0,
+                millis as winapi::DWORD,
+                QS_ALLINPUT,
+            )
+        };
+        unsafe {
+            dispatch_messages();
+        }
+        match res {
+            winapi::WAIT_OBJECT_0 => self.read(),
+            winapi::WAIT_FAILED => {
+                Error::ffi_err("MsgWaitForMultipleObjects failed")
+            }
+            _ => Ok(true),
+        }
+    }
+
+    // Reads input, waiting until there is some. Returns false once
+    // there will be no more input.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_range_loop))]
+    fn read(&mut self) -> Result<bool> {
+        let mut buffer: [winapi::INPUT_RECORD; 128] =
+            unsafe { ::std::mem::uninitialized() };
         let mut read_count: winapi::DWORD = 0;
         unsafe {
             if kernel32::ReadConsoleInputW(
-                in_hndl,
+                self.in_hndl,
                 buffer.as_mut_ptr(),
                 128,
                 &mut read_count,
@@ -241,6 +359,7 @@ fn event_loop(tx: Sender<Box<Event>>) -> Result<()> {
                 return Error::ffi_err("ReadConsoleInputW failed");
             }
         }
+        let tx = &self.tx;
         for i in 0..read_count as usize {
             let input = buffer[i];
             if input.EventType == winapi::FOCUS_EVENT
@@ -251,23 +370,26 @@ fn event_loop(tx: Sender<Box<Event>>) -> Result<()> {
             match input.EventType {
                 winapi::MOUSE_EVENT => {
                     let mevt = unsafe { input.MouseEvent() };
-                    mouse_reader.read(mevt)?
+                    self.mouse_reader.read(mevt)?
                 }
                 winapi::KEY_EVENT => {
                     let kevt = unsafe { input.KeyEvent() };
                     match kevt.wVirtualKeyCode {
-                        SHUTDOWN_KEY => return Ok(()),
+                        SHUTDOWN_KEY => return Ok(false),
                         SIGINT_KEY => tx.send(Box::new(InputEvent::Interrupt))?,
                         SIGQUIT_KEY => tx.send(Box::new(InputEvent::Break))?,
-                        _ => key_reader.read(kevt)?,
+                        _ => self.key_reader.read(kevt)?,
+                    }
+                }
+                winapi::WINDOW_BUFFER_SIZE_EVENT => {
+                    if self.resizer.update()? {
+                        tx.send(Box::new(InputEvent::Repaint))?;
                     }
                 }
-                winapi::WINDOW_BUFFER_SIZE_EVENT => if resizer.update()? {
-                    tx.send(Box::new(InputEvent::Repaint))?;
-                },
                 _ => unreachable!(),
             };
         }
+        Ok(true)
     }
 }
 
@@ -365,6 +487,25 @@ impl KeyReader {
             0x79 => Key::F10,
             0x7a => Key::F11,
             0x7b => Key::F12,
+            k if k >= 0x7c && k <= 0x87 => match Key::function(
+                (evt.wVirtualKeyCode - 0x70 + 1) as u8,
+            ) {
+                Some(key) => key,
+                None => return Ok(false),
+            },
+            0x0c => Key::KpCenter,
+            0x13 => Key::Pause,
+            0x29 => Key::Select,
+            0x2c => Key::Print,
+            0x2f => Key::Help,
+            0x5d => Key::Menu,
+            0xad => Key::VolumeMute,
+            0xae => Key::VolumeDown,
+            0xaf => Key::VolumeUp,
+            0xb0 => Key::MediaNext,
+            0xb1 => Key::MediaPrev,
+            0xb2 => Key::MediaStop,
+            0xb3 => Key::MediaPlayPause,
             _ => return Ok(false),
         };
         self.send(InputEvent::Key(skey, Mods::win32(evt.dwControlKeyState)))?;
@@ -425,13 +566,26 @@ bitflags! {
         const LEFT = 0x01;
         const RIGHT = 0x02;
         const MIDDLE = 0x04;
+        const BACK = 0x08;
+        const FORWARD = 0x10;
     }
 }
 
+const BUTTONS: [(Btn, MouseButton); 5] = [
+    (Btn::LEFT, MouseButton::Left),
+    (Btn::MIDDLE, MouseButton::Middle),
+    (Btn::RIGHT, MouseButton::Right),
+    (Btn::BACK, MouseButton::Back),
+    (Btn::FORWARD, MouseButton::Forward),
+];
+
 pub struct MouseReader {
     tx: Sender<Box<Event>>,
     coords: (i32, i32),
     btns: Btn,
+    // Whether the event being read is in the inline rows (if any);
+    // events outside them still update the buttons, but aren't sent.
+    inside: bool,
 }
 
 impl MouseReader {
@@ -440,64 +594,88 @@ impl MouseReader {
             tx,
             coords: (-1, -1),
             btns: Btn::empty(),
+            inside: true,
         }
     }
 
     fn send(&self, event: InputEvent) -> Result<()> {
-        self.tx.send(Box::new(event))?;
+        if self.inside {
+            self.tx.send(Box::new(event))?;
+        }
         Ok(())
     }
 
     fn read(&mut self, evt: &winapi::MOUSE_EVENT_RECORD) -> Result<()> {
         use input::ButtonMotion::*;
-        use input::MouseButton::*;
         use input::WheelMotion::*;
 
         let coords = (
             (evt.dwMousePosition.X as u16),
             (evt.dwMousePosition.Y as u16),
         );
+        self.inside = inline_coords(coords).is_some();
+        let coords = inline_coords(coords).unwrap_or(coords);
         let mods = Mods::win32(evt.dwControlKeyState);
         match evt.dwEventFlags {
             0 | 2 => {
-                let new_btns = Btn::from_bits(evt.dwButtonState & 0x7).unwrap();
+                let new_btns =
+                    Btn::from_bits(evt.dwButtonState & 0x1f).unwrap();
                 let presses = new_btns - self.btns;
                 let releases = self.btns - new_btns;
                 self.btns = new_btns;
-                if presses.contains(Btn::LEFT) {
-                    let mevt = InputEvent::Mouse(Press, Left, mods, coords);
-                    self.send(mevt)?;
-                }
-                if presses.contains(Btn::MIDDLE) {
-                    let mevt = InputEvent::Mouse(Press, Middle, mods, coords);
-                    self.send(mevt)?;
-                }
-                if presses.contains(Btn::RIGHT) {
-                    let mevt = InputEvent::Mouse(Press, Right, mods, coords);
-                    self.send(mevt)?;
-                }
-                if releases.contains(Btn::LEFT) {
-                    let mevt = InputEvent::Mouse(Release, Left, mods, coords);
-                    self.send(mevt)?;
-                }
-                if releases.contains(Btn::MIDDLE) {
-                    let mevt = InputEvent::Mouse(Release, Middle, mods, coords);
-                    self.send(mevt)?;
+                for &(btn, button) in &BUTTONS {
+                    if presses.contains(btn) {
+                        let mevt = InputEvent::Mouse(
+                            Press,
+                            button,
+                            mods,
+                            coords,
+                            None,
+                        );
+                        self.send(mevt)?;
+                    }
                 }
-                if releases.contains(Btn::RIGHT) {
-                    let mevt = InputEvent::Mouse(Release, Right, mods, coords);
-                    self.send(mevt)?;
+                for &(btn, button) in &BUTTONS {
+                    if releases.contains(btn) {
+                        let mevt = InputEvent::Mouse(
+                            Release,
+                            button,
+                            mods,
+                            coords,
+                            None,
+                        );
+                        self.send(mevt)?;
+                    }
                 }
             }
             1 => if (i32::from(coords.0), i32::from(coords.1)) != self.coords {
-                let mevt = InputEvent::MouseMove(mods, coords);
-                self.send(mevt)?;
+                let held = BUTTONS.iter().find(|b| self.btns.contains(b.0));
+                let mevt = match held {
+                    Some(_) if IGNORE_DRAGS.load(Ordering::SeqCst) => None,
+                    Some(&(_, button)) => {
+                        Some(InputEvent::MouseDrag(button, mods, coords, None))
+                    }
+                    None if IGNORE_MOVES.load(Ordering::SeqCst) => None,
+                    None => Some(InputEvent::MouseMove(mods, coords, None)),
+                };
+                if let Some(mevt) = mevt {
+                    self.send(mevt)?;
+                }
             },
             4 => {
                 let mevt = if (evt.dwButtonState >> 16) < 0x8000 {
-                    InputEvent::MouseWheel(Up, mods, coords)
+                    InputEvent::MouseWheel(Up, mods, coords, None)
+                } else {
+                    InputEvent::MouseWheel(Down, mods, coords, None)
+                };
+                self.send(mevt)?;
+            }
+            // MOUSE_HWHEELED
+            8 => {
+                let mevt = if (evt.dwButtonState >> 16) < 0x8000 {
+                    InputEvent::MouseWheel(Right, mods, coords, None)
                 } else {
-                    InputEvent::MouseWheel(Down, mods, coords)
+                    InputEvent::MouseWheel(Left, mods, coords, None)
                 };
                 self.send(mevt)?;
             }
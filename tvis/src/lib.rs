@@ -2,7 +2,6 @@
 extern crate bitflags;
 #[cfg(windows)]
 extern crate kernel32;
-#[cfg(not(windows))]
 #[macro_use]
 extern crate lazy_static;
 extern crate libc;
@@ -10,6 +9,8 @@ extern crate libc;
 #[macro_use]
 extern crate tinf;
 extern crate tvis_util;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 #[cfg(windows)]
 extern crate user32;
 #[cfg(windows)]
@@ -20,6 +21,7 @@ use std::sync::mpsc::SendError;
 
 pub mod term;
 pub mod input;
+pub mod width;
 
 /////////////////////////////////////////////////////////////////
 pub type Coords = (u16, u16);
@@ -112,3 +114,12 @@ const SILENCE_WARNING_FOR_TEST_ONLY_MACRO_USE: [tinf::Param; 0] = params!();
 fn is_rxvt(desc: &::tinf::Desc) -> bool {
     !desc.names().is_empty() && desc.names()[0].starts_with("rxvt-unicode")
 }
+
+// Whether the terminal understands xterm's OSC sequences.
+#[cfg(not(windows))]
+fn is_xterm(desc: &::tinf::Desc) -> bool {
+    const XTERMS: [&str; 7] =
+        ["xterm", "rxvt", "vte", "kitty", "alacritty", "foot", "iterm"];
+    !desc.names().is_empty()
+        && XTERMS.iter().any(|&x| desc.names()[0].starts_with(x))
+}
@@ -0,0 +1,214 @@
+use term::Color;
+
+/// How colors the terminal can't display are mapped to ones it can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMapping {
+    /// Choose the closest color in the CIELAB color space.
+    Perceptual,
+    /// Choose the closest color by straight RGB distance.
+    Rgb,
+}
+
+impl Default for ColorMapping {
+    fn default() -> ColorMapping {
+        ColorMapping::Perceptual
+    }
+}
+
+// The first 16 entries are xterm's defaults for the system colors,
+// which vary from terminal to terminal.
+const SYSTEM: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+lazy_static! {
+    static ref LAB: Vec<(f32, f32, f32)> =
+        (0..256).map(|i| lab(rgb(i as u8))).collect();
+}
+
+/// Returns the RGB value of entry `i` in the xterm 256-color
+/// palette.
+pub fn rgb(i: u8) -> (u8, u8, u8) {
+    let i = i as usize;
+    if i < 16 {
+        SYSTEM[i]
+    } else if i < 232 {
+        let i = i - 16;
+        (CUBE[i / 36], CUBE[(i / 6) % 6], CUBE[i % 6])
+    } else {
+        let v = (8 + (i - 232) * 10) as u8;
+        (v, v, v)
+    }
+}
+
+/// Maps `color` to the nearest color in a palette of `colors`
+/// entries.
+///
+/// Truecolor is mapped to the 240 colors after the system colors
+/// when `colors` is at least 256, and to the system colors
+/// otherwise. Palette colors that are already in range, and
+/// `Color::Default`, are returned unchanged; if `colors` is less
+/// than 8, everything maps to `Color::Default`.
+pub fn downsample(
+    color: Color,
+    colors: usize,
+    mapping: ColorMapping,
+) -> Color {
+    let rgb = match color {
+        Color::Default => return Color::Default,
+        Color::Palette(i) if (i as usize) < colors => return color,
+        Color::Palette(i) => rgb(i),
+        Color::TrueColor(r, g, b) => (r, g, b),
+    };
+    let range = if colors >= 256 {
+        16..256
+    } else if colors >= 16 {
+        0..16
+    } else if colors >= 8 {
+        0..8
+    } else {
+        return Color::Default;
+    };
+    let nearest = match mapping {
+        ColorMapping::Perceptual => {
+            let from = lab(rgb);
+            range.min_by(|&a, &b| {
+                let da = lab_dist(from, LAB[a]);
+                let db = lab_dist(from, LAB[b]);
+                da.partial_cmp(&db).unwrap()
+            })
+        }
+        ColorMapping::Rgb => {
+            range.min_by_key(|&i| rgb_dist(rgb, self::rgb(i as u8)))
+        }
+    };
+    Color::Palette(nearest.unwrap() as u8)
+}
+
+// Whether `rgb` is closer to black than to white.
+pub(crate) fn is_dark(rgb: (u8, u8, u8)) -> bool {
+    lab(rgb).0 < 50.0
+}
+
+// Guesses whether the background is dark from the `COLORFGBG`
+// environment variable, which some terminals set to "fg;bg" or
+// "fg;default;bg".
+pub(crate) fn colorfgbg_is_dark(val: &str) -> Option<bool> {
+    let bg: u8 = val.rsplit(';').next()?.parse().ok()?;
+    match bg {
+        7 => Some(false),
+        b if b < 9 => Some(true),
+        b if b < 16 => Some(false),
+        _ => None,
+    }
+}
+
+fn rgb_dist(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| {
+        let d = x as i32 - y as i32;
+        (d * d) as u32
+    };
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn lab_dist(a: (f32, f32, f32), b: (f32, f32, f32)) -> f32 {
+    let (dl, da, db) = (a.0 - b.0, a.1 - b.1, a.2 - b.2);
+    dl * dl + da * da + db * db
+}
+
+// sRGB (D65) to CIELAB.
+fn lab(rgb: (u8, u8, u8)) -> (f32, f32, f32) {
+    fn linear(c: u8) -> f32 {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    }
+    fn f(t: f32) -> f32 {
+        if t > 0.008_856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    }
+    let (r, g, b) = (linear(rgb.0), linear(rgb.1), linear(rgb.2));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.950_47;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.088_83;
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+#[cfg(test)]
+mod test {
+    use term::Color;
+    use super::*;
+
+    #[test]
+    fn dark() {
+        assert!(is_dark((0, 0, 0)));
+        assert!(is_dark((0x30, 0x0a, 0x24)));
+        assert!(!is_dark((0xfd, 0xf6, 0xe3)));
+        assert_eq!(colorfgbg_is_dark("15;0"), Some(true));
+        assert_eq!(colorfgbg_is_dark("0;default;15"), Some(false));
+        assert_eq!(colorfgbg_is_dark("0;7"), Some(false));
+        assert_eq!(colorfgbg_is_dark("0;8"), Some(true));
+        assert_eq!(colorfgbg_is_dark("default;default"), None);
+        assert_eq!(colorfgbg_is_dark(""), None);
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(rgb(16), (0, 0, 0));
+        assert_eq!(rgb(196), (255, 0, 0));
+        assert_eq!(rgb(231), (255, 255, 255));
+        assert_eq!(rgb(232), (8, 8, 8));
+        assert_eq!(rgb(255), (238, 238, 238));
+    }
+
+    #[test]
+    fn to_256() {
+        let p = ColorMapping::Perceptual;
+        let tc = Color::TrueColor;
+        assert_eq!(downsample(tc(255, 0, 0), 256, p), Color::Palette(196));
+        assert_eq!(downsample(tc(0, 0, 0), 256, p), Color::Palette(16));
+        assert_eq!(downsample(tc(128, 128, 128), 256, p), Color::Palette(244));
+        assert_eq!(downsample(tc(0, 95, 135), 256, p), Color::Palette(24));
+        let r = ColorMapping::Rgb;
+        assert_eq!(downsample(tc(250, 5, 3), 256, r), Color::Palette(196));
+        let c = Color::Palette(200);
+        assert_eq!(downsample(c, 256, p), c);
+        assert_eq!(downsample(Color::Default, 8, p), Color::Default);
+    }
+
+    #[test]
+    fn to_16_and_8() {
+        let p = ColorMapping::Perceptual;
+        let tc = Color::TrueColor;
+        assert_eq!(downsample(tc(250, 0, 0), 16, p), Color::Palette(9));
+        assert_eq!(downsample(tc(250, 0, 0), 8, p), Color::Palette(1));
+        assert_eq!(downsample(Color::Palette(196), 16, p), Color::Palette(9));
+        assert_eq!(downsample(Color::Palette(12), 8, p), Color::Palette(4));
+        assert_eq!(downsample(Color::Palette(15), 8, p), Color::Palette(7));
+        assert_eq!(downsample(tc(1, 2, 3), 0, p), Color::Default);
+    }
+}
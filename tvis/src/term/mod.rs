@@ -1,9 +1,16 @@
+#[cfg(not(windows))]
+use std::os::unix::io::RawFd;
 use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
 use std::sync::mpsc::Sender;
-use input::Event;
+use std::time::Duration;
+use input::{Event, InputEvent};
+use width::{AmbiguousWidth, WideAtEdge};
 use {Coords, Result};
+#[cfg(not(windows))]
+use tinf::Desc;
 
 pub use tvis_util::size::WinSize;
+pub use self::color::{downsample, ColorMapping};
 
 #[cfg(windows)]
 #[path = "windows.rs"]
@@ -11,6 +18,9 @@ mod platform;
 #[cfg(not(windows))]
 #[path = "unix.rs"]
 mod platform;
+mod color;
+#[cfg(not(windows))]
+mod motion;
 
 #[cfg(windows)]
 use self::platform::Term;
@@ -25,6 +35,30 @@ bitflags! {
         const BOLD = 1;
         const ITALIC = 2;
         const UNDERLINE = 4;
+        const REVERSE = 8;
+        const DIM = 16;
+        const BLINK = 32;
+        const INVISIBLE = 64;
+        const STANDOUT = 128;
+        const STRIKETHROUGH = 256;
+        const DOUBLE_UNDERLINE = 512;
+        const CURLY_UNDERLINE = 1024;
+        const DOTTED_UNDERLINE = 2048;
+    }
+}
+
+bitflags! {
+    /// The progressive enhancements of the kitty keyboard protocol.
+    #[derive(Default)]
+    pub struct KeyboardFlags: u8 {
+        /// Report Esc, and keys with modifiers, unambiguously.
+        const DISAMBIGUATE = 1;
+        /// Report key repeats and releases.
+        const EVENT_TYPES = 2;
+        /// Report the shifted key along with Shift.
+        const ALTERNATE_KEYS = 4;
+        /// Report all keys, even unmodified text, as key events.
+        const ALL_KEYS = 8;
     }
 }
 
@@ -34,7 +68,7 @@ impl Style {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Color {
     Default,
     Palette(u8),
@@ -47,6 +81,86 @@ impl Default for Color {
     }
 }
 
+/// The colors that can be changed with
+/// [`set_dynamic_color`](trait.Terminal.html#tymethod.set_dynamic_color).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DynamicColor {
+    /// The default foreground color.
+    Foreground,
+    /// The default background color.
+    Background,
+    /// The cursor color.
+    Cursor,
+}
+
+/// Where the terminal draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Screen {
+    /// The alternate screen, which leaves the normal screen and its
+    /// scrollback untouched.
+    Alternate,
+    /// `rows` lines starting at the cursor's line, scrolling the
+    /// screen up to make room if needed; row 0 is the first of them.
+    /// If `keep` is true the final frame is left in the scrollback
+    /// when the terminal is dropped, otherwise it is cleared.
+    ///
+    /// Mouse coordinates are relative to the rows too, and events
+    /// outside them are not reported. Where the rows are is learned
+    /// from the cursor position when input starts, and again by
+    /// [`query_cursor`](trait.Terminal.html#tymethod.query_cursor)
+    /// (which should be called after a resize); if the terminal can't
+    /// report it, no mouse events are reported.
+    Inline { rows: u16, keep: bool },
+}
+
+/// Which mouse events are reported, for
+/// [`set_mouse_mode`](trait.Terminal.html#tymethod.set_mouse_mode).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseMode {
+    /// No mouse events, leaving the terminal's own text selection
+    /// working.
+    None,
+    /// Button presses and releases, and the wheel.
+    Press,
+    /// Like `Press`, plus movement while a button is held down.
+    Drag,
+    /// Like `Press`, plus all movement (the default).
+    Any,
+}
+
+/// How long to wait for the rest of an escape sequence, for
+/// [`set_esc_timeout`](trait.Terminal.html#tymethod.set_esc_timeout).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscTimeout {
+    /// A fixed timeout (at most one second).
+    Fixed(Duration),
+    /// Starts at 10 milliseconds and adapts to how long sequences
+    /// take to arrive: it grows when a sequence seems to have been
+    /// split by the timeout, and shrinks when sequences arrive
+    /// quickly.
+    Adaptive,
+}
+
+/// How an escape followed by other input that isn't a known sequence
+/// is reported, for
+/// [`set_esc_policy`](trait.Terminal.html#tymethod.set_esc_policy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscPolicy {
+    /// As Alt plus the following key (the default).
+    Alt,
+    /// As `Esc` and then the following keys.
+    Esc,
+}
+
+/// Cursor shapes for
+/// [`set_cursor_shape`](trait.Terminal.html#tymethod.set_cursor_shape).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum UseTruecolor {
     /// Always support truecolor.
@@ -75,27 +189,355 @@ pub trait Terminal {
     fn is_tty_input(&self) -> bool;
     fn is_tty_output(&self) -> bool;
     fn start_input(&mut self) -> Result<()>;
+    /// Reads input on the calling thread, and returns the next input
+    /// event, or `None` if there is none within `timeout`. The first
+    /// call starts mouse and paste input, as `start_input` would.
+    ///
+    /// Panics if the terminal was connected with an input channel.
+    /// Returns an error once there will be no more input (at the end of
+    /// input, or on `SIGTERM`).
+    fn poll_event(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<InputEvent>>;
+    /// Like `poll_event`, but waits for as long as it takes.
+    fn read_event(&mut self) -> Result<InputEvent>;
     fn supported_styles(&self) -> Style;
     fn max_colors(&self) -> (usize, usize, bool);
     fn get_size(&self) -> Result<WinSize>;
     fn get_style(&self) -> Style;
     fn get_fg(&self) -> Color;
     fn get_bg(&self) -> Color;
+    /// Colors the terminal can't display are replaced by the nearest
+    /// color it can (see [`downsample`](fn.downsample.html)).
     fn set_style(&mut self, style: Style, fg: Color, bg: Color) -> Result<()>;
+    /// Sets how `set_style` chooses the nearest displayable color.
+    fn set_color_mapping(&mut self, mapping: ColorMapping);
+    /// Sets the color of underlines, if the terminal supports it.
+    fn set_underline_color(&mut self, color: Color) -> Result<()>;
+    /// Redefines palette entry `index` as the RGB color `rgb`.
+    /// Returns `false` if the terminal can't change (and later
+    /// restore) its palette. The original palette is restored when
+    /// the terminal is dropped.
+    fn set_palette(&mut self, index: u8, rgb: (u8, u8, u8)) -> Result<bool>;
+    /// Like `set_palette`, but for the default foreground,
+    /// background, or cursor color.
+    fn set_dynamic_color(
+        &mut self,
+        which: DynamicColor,
+        rgb: (u8, u8, u8),
+    ) -> Result<bool>;
+    /// Sets how long queries wait for the terminal to reply (100
+    /// milliseconds by default).
+    fn set_query_timeout(&mut self, timeout: Duration);
+    /// Asks the terminal for the RGB value of palette entry `index`.
+    /// Returns `None` if it doesn't reply in time.
+    fn query_palette(&mut self, index: u8) -> Result<Option<(u8, u8, u8)>>;
+    /// Like `query_palette`, but for the default foreground,
+    /// background, or cursor color.
+    fn query_dynamic_color(
+        &mut self,
+        which: DynamicColor,
+    ) -> Result<Option<(u8, u8, u8)>>;
+    /// Guesses whether the terminal's background is dark, from its
+    /// default background color or else the `COLORFGBG` environment
+    /// variable. Returns `None` if neither is available.
+    fn is_dark_background(&mut self) -> Result<Option<bool>>;
+    /// Asks the terminal where the cursor is. Returns `None` if it
+    /// doesn't reply in time. In inline mode the position is relative
+    /// to the inline rows, as for `set_cursor`, and is `None` if the
+    /// cursor is outside them (or if where they are is not yet known).
+    fn query_cursor(&mut self) -> Result<Option<Coords>>;
+    /// Enables the kitty keyboard protocol with `flags`, if the
+    /// terminal replies to a query for it in time. Returns `false`
+    /// otherwise, and keys are reported as before. The terminal's
+    /// previous flags are restored when it is dropped.
+    fn set_keyboard_flags(&mut self, flags: KeyboardFlags) -> Result<bool>;
+    /// Enables xterm's `modifyOtherKeys` (level 2), so that keys like
+    /// Ctrl-Shift-letters, Ctrl-digits and Alt-Enter are reported
+    /// with their modifiers; terminals without it ignore the request.
+    /// Returns `false` if input or output is redirected. It is
+    /// disabled when the terminal is dropped.
+    fn enable_modify_other_keys(&mut self) -> Result<bool>;
+    /// Enables (or disables) SGR-Pixels mouse reporting, so that mouse
+    /// events also have their position in pixels. Returns `false` if
+    /// input or output is redirected, or if the terminal doesn't
+    /// report its size in pixels (if it stops reporting it, mouse
+    /// events are dropped, since their cells are unknown).
+    fn set_mouse_pixels(&mut self, enabled: bool) -> Result<bool>;
+    /// Sets which mouse events are reported; it can be changed at any
+    /// time. The most precise encoding the terminal supports is used.
+    fn set_mouse_mode(&mut self, mode: MouseMode) -> Result<()>;
+    /// Sets how long input waits for the rest of an escape sequence
+    /// before reporting `Esc` (10 milliseconds by default). Like
+    /// `set_esc_policy`, it can be called before `start_input` or at
+    /// any time after.
+    fn set_esc_timeout(&mut self, timeout: EscTimeout);
+    /// Sets whether an escape followed by other input that isn't a
+    /// known sequence is reported as Alt plus the following key (the
+    /// default), or as `Esc` and then the following keys.
+    fn set_esc_policy(&mut self, policy: EscPolicy);
     fn set_cursor(&mut self, coords: Coords) -> Result<()>;
+    /// Scrolls the rows from `top` to `bottom` (inclusive) up by
+    /// `lines`, or down if `lines` is negative. Returns `false` if
+    /// the terminal can't scroll, in which case the rows must be
+    /// repainted.
+    fn scroll(&mut self, top: u16, bottom: u16, lines: i32) -> Result<bool>;
     fn cursor_visible(&mut self, visible: bool) -> Result<()>;
-    fn write(&mut self, text: &str) -> Result<()>;
+    /// Sets the shape of the cursor, and whether it blinks. Returns
+    /// `false` if the terminal can't change it. The user's cursor is
+    /// restored when the terminal is dropped.
+    fn set_cursor_shape(
+        &mut self,
+        shape: CursorShape,
+        blink: bool,
+    ) -> Result<bool>;
+    /// Sets the color of the cursor, like `set_cursor_shape`.
+    fn set_cursor_color(&mut self, rgb: (u8, u8, u8)) -> Result<bool>;
+    /// Writes `text`, with control characters made visible, and
+    /// returns its width in columns.
+    fn write(&mut self, text: &str) -> Result<u16>;
+    /// Like `write`, but clips `text` to at most `max_cols` columns.
+    fn write_clipped(&mut self, text: &str, max_cols: u16) -> Result<u16>;
+    /// Sets how the widths of ambiguous-width characters are
+    /// counted, and what happens to wide characters cut off by
+    /// `write_clipped`.
+    fn set_width_policy(
+        &mut self,
+        ambiguous: AmbiguousWidth,
+        edge: WideAtEdge,
+    );
+    /// Sets the window (or tab) title. Returns `false` if the
+    /// terminal has no title. The previous title is restored when the
+    /// terminal is dropped, if the terminal allows it.
+    fn set_title(&mut self, title: &str) -> Result<bool>;
+    /// Sets the icon name (the title of a minimized window), like
+    /// `set_title`.
+    fn set_icon_name(&mut self, name: &str) -> Result<bool>;
+    /// Enables (or disables) job control: Ctrl-Z and `SIGTSTP` are
+    /// sent as `InputEvent::Suspend`, which should be answered by
+    /// calling `suspend`, instead of as a key or by stopping the
+    /// process. Has no effect on Windows.
+    fn set_job_control(&mut self, enabled: bool);
+    /// Restores the terminal and stops the process, like Ctrl-Z in a
+    /// shell. When the process is continued in the foreground, the
+    /// terminal is set up again and `InputEvent::Repaint` is sent.
+    /// Does nothing on Windows.
+    fn suspend(&mut self) -> Result<()>;
     fn clear(&mut self) -> Result<()>;
     fn flush_output(&mut self) -> Result<()>;
     #[cfg(debug_assertions)]
     fn log(&mut self, text: &str);
 }
 
+/// Finds the block of rows in `old` that appears shifted up or down
+/// in `new`, so that it can be moved with a single
+/// [`scroll`](trait.Terminal.html#tymethod.scroll) instead of being
+/// repainted.
+///
+/// Returns the `(top, bottom, lines)` arguments for `scroll`, or
+/// `None` if no shifted block would save any repainting.
+pub fn find_scroll<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+) -> Option<(u16, u16, i32)> {
+    let len = ::std::cmp::min(old.len(), new.len()) as i32;
+    let mut best = None;
+    let mut best_saved = 0;
+    for shift in (1 - len)..len {
+        if shift == 0 {
+            continue;
+        }
+        let (mut start, mut saved) = (None, 0);
+        for row in 0..(len + 1) {
+            let src = row + shift;
+            let same = row < len && src >= 0 && src < len
+                && new[row as usize] == old[src as usize];
+            match (same, start) {
+                (true, None) => {
+                    start = Some(row);
+                    saved = 0;
+                }
+                (false, Some(first)) => {
+                    start = None;
+                    if saved > best_saved {
+                        best_saved = saved;
+                        let last = row - 1;
+                        best = Some(if shift > 0 {
+                            (first as u16, (last + shift) as u16, shift)
+                        } else {
+                            ((first + shift) as u16, last as u16, shift)
+                        });
+                    }
+                }
+                _ => (),
+            }
+            if same && new[row as usize] != old[row as usize] {
+                saved += 1;
+            }
+        }
+    }
+    best
+}
+
+/// Options for connecting to the terminal; see
+/// [`connect`](#method.connect).
+///
+/// ```no_run
+/// use tvis::term::{ConnectOptions, MouseMode};
+///
+/// let term = ConnectOptions::new()
+///     .mouse_mode(MouseMode::Press)
+///     .hide_cursor(true)
+///     .connect()
+///     .unwrap();
+/// ```
+pub struct ConnectOptions {
+    tx: Option<Sender<Box<Event>>>,
+    use_tc: UseTruecolor,
+    b_b: BoldOrBright,
+    screen: Screen,
+    mouse_mode: MouseMode,
+    esc_timeout: Option<EscTimeout>,
+    signals: bool,
+    tty: bool,
+    hide_cursor: bool,
+    #[cfg(not(windows))]
+    desc: Option<Desc>,
+    #[cfg(not(windows))]
+    fds: Option<(RawFd, RawFd)>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> ConnectOptions {
+        ConnectOptions::new()
+    }
+}
+
+impl ConnectOptions {
+    /// The defaults are those of `connect`: no input, automatic
+    /// truecolor, 16 colors rather than bold on 8-color terminals, the
+    /// alternate screen, reporting all mouse movement, a 10
+    /// millisecond escape timeout, and signal handling.
+    pub fn new() -> ConnectOptions {
+        ConnectOptions {
+            tx: None,
+            use_tc: UseTruecolor::Auto,
+            b_b: BoldOrBright::Bright,
+            screen: Screen::Alternate,
+            mouse_mode: MouseMode::Any,
+            esc_timeout: None,
+            signals: true,
+            tty: false,
+            hide_cursor: false,
+            #[cfg(not(windows))]
+            desc: None,
+            #[cfg(not(windows))]
+            fds: None,
+        }
+    }
+
+    /// Sends input events to `tx` once `start_input` is called.
+    pub fn input(mut self, tx: Sender<Box<Event>>) -> ConnectOptions {
+        self.tx = Some(tx);
+        self
+    }
+
+    pub fn truecolor(mut self, use_tc: UseTruecolor) -> ConnectOptions {
+        self.use_tc = use_tc;
+        self
+    }
+
+    pub fn bold_or_bright(mut self, b_b: BoldOrBright) -> ConnectOptions {
+        self.b_b = b_b;
+        self
+    }
+
+    /// Sets where the terminal draws (see
+    /// [`Screen`](enum.Screen.html)).
+    pub fn screen(mut self, screen: Screen) -> ConnectOptions {
+        self.screen = screen;
+        self
+    }
+
+    /// Sets which mouse events are reported, as with
+    /// [`set_mouse_mode`](trait.Terminal.html#tymethod.set_mouse_mode).
+    pub fn mouse_mode(mut self, mode: MouseMode) -> ConnectOptions {
+        self.mouse_mode = mode;
+        self
+    }
+
+    /// Sets the escape timeout, as with
+    /// [`set_esc_timeout`](trait.Terminal.html#tymethod.set_esc_timeout).
+    pub fn esc_timeout(mut self, timeout: EscTimeout) -> ConnectOptions {
+        self.esc_timeout = Some(timeout);
+        self
+    }
+
+    /// If `false`, no signal handlers are installed, so no `Repaint`
+    /// event is sent when the terminal is resized and the process is
+    /// not told when it is continued after being stopped. This has no
+    /// effect on Windows.
+    pub fn signals(mut self, signals: bool) -> ConnectOptions {
+        self.signals = signals;
+        self
+    }
+
+    /// If `true`, opens the controlling terminal, as with
+    /// [`connect_tty`](fn.connect_tty.html).
+    pub fn tty(mut self, tty: bool) -> ConnectOptions {
+        self.tty = tty;
+        self
+    }
+
+    /// If `true`, hides the cursor once connected.
+    pub fn hide_cursor(mut self, hide: bool) -> ConnectOptions {
+        self.hide_cursor = hide;
+        self
+    }
+
+    /// Uses `desc` instead of the description of the current terminal
+    /// (`Desc::current()`).
+    #[cfg(not(windows))]
+    pub fn desc(mut self, desc: Desc) -> ConnectOptions {
+        self.desc = Some(desc);
+        self
+    }
+
+    /// Uses the terminal on `in_fd` and `out_fd`, as with
+    /// [`connect_fd`](fn.connect_fd.html); the `tty` and `signals`
+    /// options are ignored.
+    #[cfg(not(windows))]
+    pub fn fds(mut self, in_fd: RawFd, out_fd: RawFd) -> ConnectOptions {
+        self.fds = Some((in_fd, out_fd));
+        self
+    }
+
+    pub fn connect(self) -> Result<Box<Terminal>> {
+        let mouse_mode = self.mouse_mode;
+        let esc_timeout = self.esc_timeout;
+        let hide_cursor = self.hide_cursor;
+        let mut term = Term::connect(self)?;
+        term.set_mouse_mode(mouse_mode)?;
+        if let Some(timeout) = esc_timeout {
+            term.set_esc_timeout(timeout);
+        }
+        if hide_cursor {
+            term.cursor_visible(false)?;
+            term.flush_output()?;
+        }
+        Ok(term)
+    }
+}
+
 pub fn connect(
     use_tc: UseTruecolor,
     b_b: BoldOrBright,
 ) -> Result<Box<Terminal>> {
-    Term::connect(None, use_tc, b_b)
+    ConnectOptions::new()
+        .truecolor(use_tc)
+        .bold_or_bright(b_b)
+        .connect()
 }
 
 pub fn connect_with_input(
@@ -103,5 +545,84 @@ pub fn connect_with_input(
     use_tc: UseTruecolor,
     b_b: BoldOrBright,
 ) -> Result<Box<Terminal>> {
-    Term::connect(Some(tx), use_tc, b_b)
+    ConnectOptions::new()
+        .input(tx)
+        .truecolor(use_tc)
+        .bold_or_bright(b_b)
+        .connect()
+}
+
+/// Like `connect` (or `connect_with_input`, if `tx` is not `None`),
+/// but draws on `rows` lines below the cursor instead of the
+/// alternate screen (see [`Screen::Inline`](enum.Screen.html)).
+pub fn connect_inline(
+    tx: Option<Sender<Box<Event>>>,
+    rows: u16,
+    keep: bool,
+    use_tc: UseTruecolor,
+    b_b: BoldOrBright,
+) -> Result<Box<Terminal>> {
+    ConnectOptions { tx, ..ConnectOptions::new() }
+        .truecolor(use_tc)
+        .bold_or_bright(b_b)
+        .screen(Screen::Inline { rows, keep })
+        .connect()
+}
+
+/// Like `connect` (or `connect_with_input`, if `tx` is not `None`),
+/// but opens the controlling terminal (`/dev/tty`) for terminal input
+/// and output, leaving stdin and stdout free for data, as in a
+/// pipeline. On Windows the console is always used, so this is the
+/// same as `connect`.
+pub fn connect_tty(
+    tx: Option<Sender<Box<Event>>>,
+    use_tc: UseTruecolor,
+    b_b: BoldOrBright,
+) -> Result<Box<Terminal>> {
+    ConnectOptions { tx, ..ConnectOptions::new() }
+        .truecolor(use_tc)
+        .bold_or_bright(b_b)
+        .tty(true)
+        .connect()
+}
+
+/// Connects to the terminal (or pseudo-terminal) on `in_fd` and
+/// `out_fd`, described by `desc`, which need not be the process's own
+/// terminal; any number of these can be connected at once.
+///
+/// The descriptors are not closed when the terminal is dropped, and
+/// must stay open until it is: the input thread (if `start_input` was
+/// called) is stopped and joined when the terminal is dropped. Since
+/// it is not the controlling terminal, there is no signal handling or
+/// job control: `suspend` does nothing, and no `Repaint` event is sent
+/// when it is resized (`get_size` always returns its current size).
+#[cfg(not(windows))]
+pub fn connect_fd(
+    in_fd: RawFd,
+    out_fd: RawFd,
+    desc: &Desc,
+    tx: Option<Sender<Box<Event>>>,
+    use_tc: UseTruecolor,
+    b_b: BoldOrBright,
+) -> Result<Box<Terminal>> {
+    ConnectOptions { tx, ..ConnectOptions::new() }
+        .truecolor(use_tc)
+        .bold_or_bright(b_b)
+        .desc(desc.clone())
+        .fds(in_fd, out_fd)
+        .connect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::find_scroll;
+
+    #[test]
+    fn scroll_detection() {
+        let old = [1, 2, 3, 4, 5, 6];
+        assert_eq!(find_scroll(&old, &[1, 2, 3, 4, 5, 6]), None);
+        assert_eq!(find_scroll(&old, &[1, 3, 4, 5, 6, 0]), Some((1, 5, 1)));
+        assert_eq!(find_scroll(&old, &[0, 0, 1, 2, 3, 6]), Some((0, 4, -2)));
+        assert_eq!(find_scroll(&old, &[7, 8, 9, 7, 8, 9]), None);
+    }
 }
@@ -0,0 +1,457 @@
+#![cfg(not(windows))]
+
+use std::io::Write;
+use tinf::{cap, tparm, Desc, Vars};
+use term::WinSize;
+use {Coords, Result};
+
+// Plans cursor movement by choosing the shortest byte sequence that
+// the terminal's capabilities allow, given what is known about the
+// current cursor position and the text on the current line.
+pub(in term) struct Motion {
+    cup: Vec<u8>,
+    hpa: Vec<u8>,
+    vpa: Vec<u8>,
+    cuf: Vec<u8>,
+    cub: Vec<u8>,
+    cuu: Vec<u8>,
+    cud: Vec<u8>,
+    cuf1: Vec<u8>,
+    cub1: Vec<u8>,
+    cuu1: Vec<u8>,
+    cud1: Vec<u8>,
+    cr: Vec<u8>,
+    home: Vec<u8>,
+    ll: Vec<u8>,
+    ht: Vec<u8>,
+    tab_width: u16,
+    pos: Option<Coords>,
+    region: Option<(u16, u16)>,
+    // The printable ASCII bytes written to the cursor's line, along
+    // with the style generation they were written in (0 means the
+    // byte is unknown).
+    line: Vec<(u8, u32)>,
+    style_gen: u32,
+}
+
+impl Motion {
+    pub(in term) fn new(desc: &Desc) -> Motion {
+        // Tabs are only usable if they are non-destructive and the
+        // tab stops are known.
+        let tab_width = match desc[cap::it] {
+            0xffff => 0,
+            _ if desc[cap::xt] => 0,
+            n => n,
+        };
+        Motion {
+            cup: desc[cap::cup].to_vec(),
+            hpa: desc[cap::hpa].to_vec(),
+            vpa: desc[cap::vpa].to_vec(),
+            cuf: desc[cap::cuf].to_vec(),
+            cub: desc[cap::cub].to_vec(),
+            cuu: desc[cap::cuu].to_vec(),
+            cud: desc[cap::cud].to_vec(),
+            cuf1: desc[cap::cuf1].to_vec(),
+            cub1: desc[cap::cub1].to_vec(),
+            cuu1: desc[cap::cuu1].to_vec(),
+            cud1: desc[cap::cud1].to_vec(),
+            cr: desc[cap::cr].to_vec(),
+            home: desc[cap::home].to_vec(),
+            ll: desc[cap::ll].to_vec(),
+            ht: desc[cap::ht].to_vec(),
+            tab_width: if desc[cap::ht].is_empty() { 0 } else { tab_width },
+            pos: None,
+            region: None,
+            line: Vec::new(),
+            style_gen: 1,
+        }
+    }
+
+    // A planner that only moves the cursor relative to its current
+    // position (apart from moving to the start of the line), for use
+    // when the rows being drawn on are not at a known place on the
+    // screen.
+    pub(in term) fn relative(desc: &Desc) -> Motion {
+        let mut motion = Motion::new(desc);
+        motion.cup.clear();
+        motion.vpa.clear();
+        motion.home.clear();
+        motion.ll.clear();
+        motion
+    }
+
+    pub(in term) fn pos(&self) -> Option<Coords> {
+        self.pos
+    }
+
+    // Set the cursor position after an operation with a known
+    // effect on the cursor (or `None` if the effect is unknown).
+    pub(in term) fn set_pos(&mut self, pos: Option<Coords>) {
+        if pos.map(|p| p.1) != self.pos.map(|p| p.1) {
+            self.line.clear();
+        }
+        self.pos = pos;
+    }
+
+    // Relative vertical motion stops at the margins of a scroll
+    // region, so it is only used when it does not cross them.
+    pub(in term) fn set_region(&mut self, region: Option<(u16, u16)>) {
+        self.region = region;
+    }
+
+    // Forget all known text, for example after the screen has been
+    // cleared or scrolled.
+    pub(in term) fn forget_text(&mut self) {
+        self.line.clear();
+    }
+
+    // Text written after a style change can't be rewritten to move
+    // the cursor.
+    pub(in term) fn style_changed(&mut self) {
+        self.style_gen = self.style_gen.wrapping_add(1);
+        if self.style_gen == 0 {
+            self.style_gen = 1;
+            self.line.clear();
+        }
+    }
+
+    // Update the cursor position after `text`, which has no control
+    // characters and is `cols` columns wide, has been written.
+    pub(in term) fn advance(
+        &mut self,
+        text: &str,
+        cols: usize,
+        size: Option<WinSize>,
+    ) {
+        let (x, y) = match self.pos {
+            Some(pos) => (pos.0 as usize, pos.1),
+            None => return,
+        };
+        if self.line.len() < x + cols {
+            self.line.resize(x + cols, (0, 0));
+        }
+        // Only ASCII characters can be rewritten; others may be as
+        // many bytes as columns (ambiguous width characters, when
+        // they are wide).
+        if text.is_ascii() {
+            for (i, b) in text.bytes().enumerate() {
+                self.line[x + i] = (b, self.style_gen);
+            }
+        } else {
+            for cell in &mut self.line[x..x + cols] {
+                *cell = (0, 0);
+            }
+        }
+        // The cursor position after writing in the last column
+        // varies between terminals.
+        match size {
+            Some(size) if x + cols < size.cols as usize => {
+                self.pos = Some(((x + cols) as u16, y))
+            }
+            _ => self.set_pos(None),
+        }
+    }
+
+    // Move the cursor to `to`, using the cheapest sequence.
+    pub(in term) fn move_to(
+        &mut self,
+        w: &mut Write,
+        to: Coords,
+        size: Option<WinSize>,
+    ) -> Result<()> {
+        if self.pos == Some(to) {
+            return Ok(());
+        }
+        let seq = self.plan(to, size)?;
+        w.write_all(&seq)?;
+        if seq.is_empty() {
+            self.set_pos(None);
+        } else {
+            self.set_pos(Some(to));
+        }
+        Ok(())
+    }
+
+    fn plan(&self, to: Coords, size: Option<WinSize>) -> Result<Vec<u8>> {
+        let mut best = None;
+        if !self.cup.is_empty() {
+            let mut seq = Vec::new();
+            let mut params = params!(to.1, to.0);
+            tparm(&mut seq, &self.cup, &mut params, &mut Vars::new())?;
+            best = Some(seq);
+        }
+        let on_screen = match size {
+            Some(size) => to.0 < size.cols && to.1 < size.rows,
+            None => false,
+        };
+        if on_screen {
+            if let Some(from) = self.pos {
+                // Move horizontally first, so that known text on the
+                // current line can be rewritten.
+                self.consider(&mut best, &[], from, to, true)?;
+                if !self.cr.is_empty() {
+                    let from = (0, from.1);
+                    self.consider(&mut best, &self.cr, from, to, true)?;
+                }
+            }
+            if !self.home.is_empty() {
+                self.consider(&mut best, &self.home, (0, 0), to, false)?;
+            }
+            if let (false, Some(size)) = (self.ll.is_empty(), size) {
+                let from = (0, size.rows - 1);
+                self.consider(&mut best, &self.ll, from, to, false)?;
+            }
+        }
+        Ok(best.unwrap_or_default())
+    }
+
+    // Replace `best` with `prefix` plus a relative move from `from`
+    // to `to`, if that is possible and shorter.
+    fn consider(
+        &self,
+        best: &mut Option<Vec<u8>>,
+        prefix: &[u8],
+        from: Coords,
+        to: Coords,
+        rewrite: bool,
+    ) -> Result<()> {
+        let horiz = self.horizontal(from.0, to.0, rewrite)?;
+        let vert = self.vertical(from.1, to.1)?;
+        if let (Some(horiz), Some(vert)) = (horiz, vert) {
+            let mut seq = prefix.to_vec();
+            seq.extend(horiz);
+            seq.extend(vert);
+            keep_shorter(best, seq);
+        }
+        Ok(())
+    }
+
+    fn horizontal(
+        &self,
+        from: u16,
+        to: u16,
+        rewrite: bool,
+    ) -> Result<Option<Vec<u8>>> {
+        if from == to {
+            return Ok(Some(Vec::new()));
+        }
+        let mut best = None;
+        if !self.hpa.is_empty() {
+            let mut seq = Vec::new();
+            tparm(&mut seq, &self.hpa, &mut params!(to), &mut Vars::new())?;
+            best = Some(seq);
+        }
+        if to < from {
+            self.step(&mut best, &self.cub, &self.cub1, from - to)?;
+            return Ok(best);
+        }
+        self.step(&mut best, &self.cuf, &self.cuf1, to - from)?;
+        if self.tab_width > 0 {
+            let mut x = from;
+            let mut tabs = Vec::new();
+            while (x / self.tab_width + 1) * self.tab_width <= to {
+                x = (x / self.tab_width + 1) * self.tab_width;
+                tabs.extend(&self.ht);
+            }
+            let mut rest = None;
+            self.step(&mut rest, &self.cuf, &self.cuf1, to - x)?;
+            if let (true, Some(rest)) = (x > from, rest) {
+                tabs.extend(rest);
+                keep_shorter(&mut best, tabs);
+            }
+        }
+        if rewrite {
+            if let Some(text) = self.known_text(from, to) {
+                keep_shorter(&mut best, text);
+            }
+        }
+        Ok(best)
+    }
+
+    fn vertical(&self, from: u16, to: u16) -> Result<Option<Vec<u8>>> {
+        if from == to {
+            return Ok(Some(Vec::new()));
+        }
+        let mut best = None;
+        if !self.vpa.is_empty() {
+            let mut seq = Vec::new();
+            tparm(&mut seq, &self.vpa, &mut params!(to), &mut Vars::new())?;
+            best = Some(seq);
+        }
+        if let Some((top, bottom)) = self.region {
+            let side = |y| (y >= top) as u8 + (y > bottom) as u8;
+            if side(from) != side(to) {
+                return Ok(best);
+            }
+        }
+        if to < from {
+            self.step(&mut best, &self.cuu, &self.cuu1, from - to)?;
+        } else {
+            self.step(&mut best, &self.cud, &self.cud1, to - from)?;
+        }
+        Ok(best)
+    }
+
+    // Consider a move of `n` steps using either the parameterized
+    // capability `cap` or repetitions of the one-step capability
+    // `cap1`.
+    fn step(
+        &self,
+        best: &mut Option<Vec<u8>>,
+        cap: &[u8],
+        cap1: &[u8],
+        n: u16,
+    ) -> Result<()> {
+        if n == 0 {
+            *best = Some(Vec::new());
+            return Ok(());
+        }
+        if !cap.is_empty() {
+            let mut seq = Vec::new();
+            tparm(&mut seq, cap, &mut params!(n), &mut Vars::new())?;
+            keep_shorter(best, seq);
+        }
+        if !cap1.is_empty() {
+            let len = cap1.len() * n as usize;
+            if best.as_ref().map_or(true, |b| len < b.len()) {
+                *best = Some(cap1.repeat(n as usize));
+            }
+        }
+        Ok(())
+    }
+
+    // The text between `from` and `to` on the current line, if it
+    // is all known and was written in the current style.
+    fn known_text(&self, from: u16, to: u16) -> Option<Vec<u8>> {
+        if self.line.len() < to as usize {
+            return None;
+        }
+        let cells = &self.line[from as usize..to as usize];
+        if cells.iter().all(|&(b, g)| b != 0 && g == self.style_gen) {
+            Some(cells.iter().map(|&(b, _)| b).collect())
+        } else {
+            None
+        }
+    }
+}
+
+fn keep_shorter(best: &mut Option<Vec<u8>>, seq: Vec<u8>) {
+    if best.as_ref().map_or(true, |b| seq.len() < b.len()) {
+        *best = Some(seq);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tinf::Desc;
+    use term::WinSize;
+    use super::Motion;
+
+    fn xterm_desc() -> Desc {
+        use tinf::cap::*;
+        desc![
+            "xterm", "xterm",
+            it => 8,
+            cup => "\x1b[%i%p1%d;%p2%dH",
+            hpa => "\x1b[%i%p1%dG",
+            vpa => "\x1b[%i%p1%dd",
+            cuf => "\x1b[%p1%dC",
+            cub => "\x1b[%p1%dD",
+            cuu => "\x1b[%p1%dA",
+            cud => "\x1b[%p1%dB",
+            cuf1 => "\x1b[C",
+            cub1 => "\x08",
+            cuu1 => "\x1b[A",
+            cud1 => "\n",
+            cr => "\r",
+            home => "\x1b[H",
+            ht => "\t",
+        ]
+    }
+
+    fn size() -> Option<WinSize> {
+        Some(WinSize { cols: 80, rows: 24 })
+    }
+
+    fn move_to(motion: &mut Motion, to: (u16, u16)) -> Vec<u8> {
+        let mut w: Vec<u8> = Vec::new();
+        motion.move_to(&mut w, to, size()).unwrap();
+        w
+    }
+
+    #[test]
+    fn unknown_position() {
+        let mut motion = Motion::new(&xterm_desc());
+        assert_eq!(move_to(&mut motion, (10, 5)), b"\x1b[6;11H".to_vec());
+        assert_eq!(move_to(&mut motion, (10, 5)), b"".to_vec());
+
+        let mut motion = Motion::new(&xterm_desc());
+        assert_eq!(move_to(&mut motion, (0, 0)), b"\x1b[H".to_vec());
+    }
+
+    #[test]
+    fn relative() {
+        let mut motion = Motion::new(&xterm_desc());
+        motion.set_pos(Some((10, 5)));
+        assert_eq!(move_to(&mut motion, (11, 5)), b"\x1b[C".to_vec());
+        assert_eq!(move_to(&mut motion, (10, 5)), b"\x08".to_vec());
+        assert_eq!(move_to(&mut motion, (10, 7)), b"\n\n".to_vec());
+        assert_eq!(move_to(&mut motion, (10, 2)), b"\x1b[3d".to_vec());
+        assert_eq!(move_to(&mut motion, (0, 3)), b"\r\n".to_vec());
+        assert_eq!(move_to(&mut motion, (50, 3)), b"\x1b[51G".to_vec());
+    }
+
+    #[test]
+    fn tabs() {
+        let mut motion = Motion::new(&xterm_desc());
+        motion.set_pos(Some((1, 0)));
+        assert_eq!(move_to(&mut motion, (16, 0)), b"\t\t".to_vec());
+        assert_eq!(move_to(&mut motion, (24, 0)), b"\t".to_vec());
+    }
+
+    #[test]
+    fn rewrite() {
+        let mut motion = Motion::new(&xterm_desc());
+        motion.set_pos(Some((0, 0)));
+        motion.advance("abcd", 4, size());
+        assert_eq!(motion.pos(), Some((4, 0)));
+        assert_eq!(move_to(&mut motion, (1, 0)), b"\ra".to_vec());
+        assert_eq!(move_to(&mut motion, (3, 0)), b"bc".to_vec());
+
+        motion.style_changed();
+        motion.set_pos(Some((0, 0)));
+        assert_eq!(move_to(&mut motion, (2, 0)), b"\x1b[3G".to_vec());
+    }
+
+    #[test]
+    fn region() {
+        let mut motion = Motion::new(&xterm_desc());
+        motion.set_region(Some((5, 10)));
+        motion.set_pos(Some((0, 4)));
+        assert_eq!(move_to(&mut motion, (0, 6)), b"\x1b[7d".to_vec());
+        assert_eq!(move_to(&mut motion, (0, 8)), b"\n\n".to_vec());
+    }
+
+    #[test]
+    fn lose_position() {
+        let mut motion = Motion::new(&xterm_desc());
+        motion.set_pos(Some((78, 0)));
+        motion.advance("ab", 2, size());
+        assert_eq!(motion.pos(), None);
+
+        motion.set_pos(Some((0, 0)));
+        motion.advance("日本", 4, size());
+        assert_eq!(motion.pos(), Some((4, 0)));
+        assert_eq!(move_to(&mut motion, (3, 0)), b"\x08".to_vec());
+    }
+
+    #[test]
+    fn ambiguous_wide() {
+        let mut motion = Motion::new(&xterm_desc());
+        motion.set_pos(Some((0, 0)));
+        // With AmbiguousWidth::Wide, "±" is two bytes and two columns.
+        motion.advance("±", 2, size());
+        motion.set_pos(Some((0, 0)));
+        assert_eq!(move_to(&mut motion, (1, 0)), b"\x1b[C".to_vec());
+    }
+}
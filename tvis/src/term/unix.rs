@@ -1,16 +1,27 @@
 #![cfg(not(windows))]
 
+use std::{env, fs};
+use std::cell::Cell;
 use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
 use std::sync::atomic::Ordering;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
 use libc;
 use tinf::{tparm, Desc};
 use tinf::cap::{self, UserDef};
-use tvis_util::{Handle, TerminalMode};
-use tvis_util::size::get_size;
-use input::Event;
-use term::{BoldOrBright, Color, Style, Terminal, UseTruecolor, WinSize, TERM};
-use {is_rxvt, Coords, Error, Result};
+use tvis_util::TerminalMode;
+use tvis_util::size::get_fd_size;
+use input::{self, Event, EventLoop, InputEvent, InputThread, Reader,
+            Reply, Shared};
+use term::{BoldOrBright, Color, ColorMapping, ConnectOptions, CursorShape,
+           DynamicColor, EscPolicy, EscTimeout, KeyboardFlags, MouseMode,
+           Screen, Style, Terminal, UseTruecolor, WinSize, TERM};
+use term::color;
+use term::motion::Motion;
+use width::{self, AmbiguousWidth, WideAtEdge};
+use {is_rxvt, is_xterm, Coords, Error, Result};
 
 lazy_static! {
     static ref STDOUT: io::Stdout = io::stdout();
@@ -24,6 +35,16 @@ struct Styles {
     ritm: Vec<u8>,
     smul: Vec<u8>,
     rmul: Vec<u8>,
+    smulx: Vec<u8>,
+    rev: Vec<u8>,
+    dim: Vec<u8>,
+    blink: Vec<u8>,
+    invis: Vec<u8>,
+    smso: Vec<u8>,
+    rmso: Vec<u8>,
+    smxx: Vec<u8>,
+    rmxx: Vec<u8>,
+    setulc: Vec<u8>,
     op: Vec<u8>,
     setaf: Vec<u8>,
     setab: Vec<u8>,
@@ -34,7 +55,11 @@ struct Styles {
     fg: Color,
     bg: Color,
     style: Style,
+    // The requested underline color, and the one currently set.
+    ul_color: Color,
+    ul_color_set: Color,
     bright16: bool,
+    mapping: ColorMapping,
 }
 
 impl Styles {
@@ -68,7 +93,30 @@ impl Styles {
         if (styles.colors == 0) || ((ncv & 0x02) == 0) {
             styles.smul = desc[cap::smul].to_vec();
             styles.rmul = desc[cap::rmul].to_vec();
+            if !styles.smul.is_empty() {
+                let smulx = UserDef::named("Smulx");
+                styles.smulx = desc.get_str_ext(&smulx).to_vec();
+            }
+        }
+        if (styles.colors == 0) || ((ncv & 0x04) == 0) {
+            styles.rev = desc[cap::rev].to_vec();
+        }
+        if (styles.colors == 0) || ((ncv & 0x10) == 0) {
+            styles.dim = desc[cap::dim].to_vec();
+        }
+        if (styles.colors == 0) || ((ncv & 0x08) == 0) {
+            styles.blink = desc[cap::blink].to_vec();
         }
+        if (styles.colors == 0) || ((ncv & 0x40) == 0) {
+            styles.invis = desc[cap::invis].to_vec();
+        }
+        if (styles.colors == 0) || ((ncv & 0x01) == 0) {
+            styles.smso = desc[cap::smso].to_vec();
+            styles.rmso = desc[cap::rmso].to_vec();
+        }
+        styles.smxx = desc.get_str_ext(&UserDef::named("smxx")).to_vec();
+        styles.rmxx = desc.get_str_ext(&UserDef::named("rmxx")).to_vec();
+        styles.setulc = desc.get_str_ext(&UserDef::named("Setulc")).to_vec();
 
         // If we can't return to the default colors, or there aren't
         // any, don't activate color support.
@@ -108,19 +156,35 @@ impl Styles {
     }
 
     fn supported_styles(&self) -> Style {
-        let mut supported = Style::empty();
-        if !self.bold.is_empty() && !self.bright16 {
-            supported |= Style::BOLD;
-        }
-        if !self.sitm.is_empty() {
-            supported |= Style::ITALIC;
-        }
-        if !self.smul.is_empty() {
-            supported |= Style::UNDERLINE;
+        let mut supported = self.available_styles();
+        if self.bright16 {
+            supported.remove(Style::BOLD);
         }
         supported
     }
 
+    // The styles that have capabilities, including bold when it is
+    // used for bright colors.
+    fn available_styles(&self) -> Style {
+        let caps = [
+            (Style::BOLD, &self.bold),
+            (Style::ITALIC, &self.sitm),
+            (Style::UNDERLINE, &self.smul),
+            (Style::DOUBLE_UNDERLINE, &self.smulx),
+            (Style::CURLY_UNDERLINE, &self.smulx),
+            (Style::DOTTED_UNDERLINE, &self.smulx),
+            (Style::REVERSE, &self.rev),
+            (Style::DIM, &self.dim),
+            (Style::BLINK, &self.blink),
+            (Style::INVISIBLE, &self.invis),
+            (Style::STANDOUT, &self.smso),
+            (Style::STRIKETHROUGH, &self.smxx),
+        ];
+        caps.iter()
+            .filter(|&&(_, cap)| !cap.is_empty())
+            .fold(Style::empty(), |styles, &(style, _)| styles | style)
+    }
+
     fn max_colors(&self) -> (usize, usize, bool) {
         let tc = !self.setftc.is_empty();
         let bgs = if self.bright16 {
@@ -138,8 +202,12 @@ impl Styles {
         bg: Color,
         style: Style,
     ) -> Result<()> {
+        let (fgs, bgs, _) = self.max_colors();
+        let tc = !self.setftc.is_empty();
+        let mut fg = self.map_color(fg, fgs, tc);
+        let tc = !self.setbtc.is_empty();
+        let bg = self.map_color(bg, bgs, tc);
         let mut style = style;
-        let mut fg = fg;
         if self.bright16 {
             match fg {
                 Color::Palette(i) if i > 7 && i < 16 => {
@@ -152,6 +220,13 @@ impl Styles {
         self.sgr_(w, fg, bg, style)
     }
 
+    fn map_color(&self, color: Color, colors: usize, tc: bool) -> Color {
+        match color {
+            Color::TrueColor(..) if tc => color,
+            _ => color::downsample(color, colors, self.mapping),
+        }
+    }
+
     fn sgr_(
         &mut self,
         w: &mut Write,
@@ -163,33 +238,81 @@ impl Styles {
             return Ok(());
         }
 
+        let style = style & self.available_styles();
         let clears = self.style - style;
         let sets = style - self.style;
-        if clears.contains(Style::BOLD)
-            || (1 + style.count()) <= (clears.count() + sets.count())
-        {
+        let (ul_from, ul_to) = (underline(self.style), underline(style));
+        let toggles = [
+            (Style::ITALIC, &self.sitm, &self.ritm),
+            (Style::STANDOUT, &self.smso, &self.rmso),
+            (Style::STRIKETHROUGH, &self.smxx, &self.rmxx),
+        ];
+        // Some styles can only be turned off by resetting all of them.
+        let no_exit = Style::BOLD | Style::REVERSE | Style::DIM | Style::BLINK
+            | Style::INVISIBLE;
+        let reset = clears.intersects(no_exit)
+            || toggles
+                .iter()
+                .any(|&(s, _, off)| clears.contains(s) && off.is_empty())
+            || (ul_from != 0 && ul_to == 0 && self.rmul.is_empty()
+                && self.smulx.is_empty());
+        // The underline styles count as one style, since they replace
+        // each other.
+        let count = (style - underlines()).count() + (ul_to != 0) as u32;
+        let changes = ((clears | sets) - underlines()).count()
+            + (ul_to != ul_from) as u32;
+        if reset || (1 + count) <= changes {
             self.sgr0(w)?;
-            self.sgr(w, fg, bg, style)?;
+            self.sgr_(w, fg, bg, style)?;
         } else {
-            if clears.contains(Style::ITALIC) && !self.ritm.is_empty() {
-                w.write_all(&self.ritm)?;
-                self.style.remove(Style::ITALIC);
+            let mut curr = self.style;
+            for &(s, _, off) in &toggles {
+                if clears.contains(s) {
+                    w.write_all(off)?;
+                    curr.remove(s);
+                }
+            }
+            if ul_to == 0 && ul_from != 0 {
+                if self.rmul.is_empty() {
+                    let s = &self.smulx;
+                    tparm(w, s, &mut params!(0), &mut self.vars)?;
+                } else {
+                    w.write_all(&self.rmul)?;
+                }
+                curr.remove(underlines());
             }
-            if clears.contains(Style::UNDERLINE) && !self.rmul.is_empty() {
-                w.write_all(&self.rmul)?;
-                self.style.remove(Style::UNDERLINE);
+            let simple = [
+                (Style::BOLD, &self.bold),
+                (Style::REVERSE, &self.rev),
+                (Style::DIM, &self.dim),
+                (Style::BLINK, &self.blink),
+                (Style::INVISIBLE, &self.invis),
+            ];
+            for &(s, on) in &simple {
+                if sets.contains(s) {
+                    w.write_all(on)?;
+                    curr.insert(s);
+                }
             }
-            if sets.contains(Style::BOLD) && !self.bold.is_empty() {
-                w.write_all(&self.bold)?;
-                self.style.insert(Style::BOLD);
+            for &(s, on, _) in &toggles {
+                if sets.contains(s) {
+                    w.write_all(on)?;
+                    curr.insert(s);
+                }
             }
-            if sets.contains(Style::ITALIC) && !self.sitm.is_empty() {
-                w.write_all(&self.sitm)?;
-                self.style.insert(Style::ITALIC);
+            if ul_to != 0 && ul_to != ul_from {
+                if ul_to == 1 {
+                    w.write_all(&self.smul)?;
+                } else {
+                    let s = &self.smulx;
+                    tparm(w, s, &mut params!(ul_to), &mut self.vars)?;
+                }
+                curr = (curr - underlines()) | (style & underlines());
             }
-            if sets.contains(Style::UNDERLINE) && !self.smul.is_empty() {
-                w.write_all(&self.smul)?;
-                self.style.insert(Style::UNDERLINE);
+            self.style = curr;
+            if self.ul_color != self.ul_color_set {
+                let color = self.ul_color;
+                self.set_ul_color(w, color)?;
             }
             if self.colors == 0 {
                 return Ok(());
@@ -235,238 +358,1755 @@ impl Styles {
         Ok(())
     }
 
+    fn set_ul_color(&mut self, w: &mut Write, color: Color) -> Result<()> {
+        self.ul_color = color;
+        // Setulc takes direct colors, but palette colors are
+        // downsampled to those the terminal has, as for `sgr`.
+        let colors = self.colors;
+        let color = self.map_color(color, colors, true);
+        if self.setulc.is_empty() || color == self.ul_color_set {
+            return Ok(());
+        }
+        // There are no standard capabilities for these.
+        match color {
+            Color::Default => w.write_all(b"\x1b[59m")?,
+            Color::Palette(i) => write!(w, "\x1b[58:5:{}m", i)?,
+            Color::TrueColor(r, g, b) => {
+                let rgb = (i32::from(r) << 16) | (i32::from(g) << 8)
+                    | i32::from(b);
+                let s = &self.setulc;
+                tparm(w, s, &mut params!(rgb), &mut self.vars)?;
+            }
+        }
+        self.ul_color_set = color;
+        Ok(())
+    }
+
     fn sgr0(&mut self, w: &mut Write) -> Result<()> {
         self.style = Style::empty();
         self.fg = Color::Default;
         self.bg = Color::Default;
+        self.ul_color_set = Color::Default;
         w.write_all(&self.sgr0)?;
         Ok(())
     }
 }
 
-pub(in term) struct Term<'a> {
-    styles: Styles,
-    cup: Vec<u8>,
-    smcup: Vec<u8>,
-    rmcup: Vec<u8>,
-    civis: Vec<u8>,
-    cnorm: Vec<u8>,
-    clear: Vec<u8>,
-    stdout: io::StdoutLock<'a>,
-    init_ios: libc::termios,
-    tx: Option<Sender<Box<Event>>>,
-    tmode: (TerminalMode, TerminalMode),
-    rxvt: bool,
+fn underlines() -> Style {
+    Style::UNDERLINE | Style::DOUBLE_UNDERLINE | Style::CURLY_UNDERLINE
+        | Style::DOTTED_UNDERLINE
 }
 
-impl<'a> Term<'a> {
-    pub(in term) fn connect(
-        tx: Option<Sender<Box<Event>>>,
-        use_tc: UseTruecolor,
-        b_b: BoldOrBright,
-    ) -> Result<Box<Terminal>> {
-        if TERM.compare_and_swap(false, true, Ordering::SeqCst) {
-            panic!("TODO: better singleton panic message");
-        }
-        let init_ios = Term::set_ios()?;
-        let desc = Desc::current();
-        let mut term = Term {
-            styles: Styles::new(desc, use_tc, b_b),
-            stdout: STDOUT.lock(),
-            cup: desc[cap::cup].to_vec(),
-            smcup: desc[cap::smcup].to_vec(),
-            rmcup: desc[cap::rmcup].to_vec(),
-            civis: desc[cap::civis].to_vec(),
-            cnorm: desc[cap::cnorm].to_vec(),
-            clear: desc[cap::clear].to_vec(),
-            tmode: (
-                Handle::Stdout.terminal_mode(),
-                Handle::Stdin.terminal_mode(),
-            ),
-            rxvt: is_rxvt(desc),
-            init_ios,
-            tx,
+// The `Smulx` parameter for the underline in `style`.
+fn underline(style: Style) -> u8 {
+    if style.contains(Style::CURLY_UNDERLINE) {
+        3
+    } else if style.contains(Style::DOTTED_UNDERLINE) {
+        4
+    } else if style.contains(Style::DOUBLE_UNDERLINE) {
+        2
+    } else if style.contains(Style::UNDERLINE) {
+        1
+    } else {
+        0
+    }
+}
+
+// Scrolls part of the screen, using a scroll region if possible
+// and otherwise deleting and inserting lines.
+#[derive(Default)]
+struct Scroller {
+    csr: Vec<u8>,
+    ind: Vec<u8>,
+    indn: Vec<u8>,
+    ri: Vec<u8>,
+    rin: Vec<u8>,
+    il: Vec<u8>,
+    il1: Vec<u8>,
+    dl: Vec<u8>,
+    dl1: Vec<u8>,
+    // The scroll region last set with csr, and the number of rows
+    // on the screen when it was set.
+    region: Option<(u16, u16, u16)>,
+}
+
+impl Scroller {
+    fn new(desc: &Desc) -> Scroller {
+        let mut scroller = Scroller {
+            il: desc[cap::il].to_vec(),
+            il1: desc[cap::il1].to_vec(),
+            dl: desc[cap::dl].to_vec(),
+            dl1: desc[cap::dl1].to_vec(),
+            ..Default::default()
         };
-        term.init()?;
-        Ok(Box::new(term))
+        if !desc[cap::csr].is_empty() {
+            scroller.csr = desc[cap::csr].to_vec();
+            scroller.ind = desc[cap::ind].to_vec();
+            scroller.indn = desc[cap::indn].to_vec();
+            scroller.ri = desc[cap::ri].to_vec();
+            scroller.rin = desc[cap::rin].to_vec();
+        }
+        scroller
     }
 
-    fn set_ios() -> Result<libc::termios> {
-        unsafe {
-            let mut init_ios = ::std::mem::zeroed();
-            if 0 != libc::tcgetattr(0, &mut init_ios) {
-                return Error::ffi_err("tcgetattr failed");
-            }
-            let mut ios = init_ios;
-            libc::cfmakeraw(&mut ios);
-            if 0 != libc::tcsetattr(0, 0, &ios) {
-                return Error::ffi_err("tcsetattr failed");
+    fn scroll(
+        &mut self,
+        w: &mut Write,
+        motion: &mut Motion,
+        rows: (u16, u16),
+        lines: i32,
+        size: WinSize,
+    ) -> Result<bool> {
+        let (top, bottom) = rows;
+        if top > bottom || bottom >= size.rows {
+            return Ok(false);
+        }
+        if lines == 0 {
+            return Ok(true);
+        }
+        let height = i32::from(bottom - top + 1);
+        let lines_abs = lines.checked_abs().unwrap_or(i32::max_value());
+        let n = ::std::cmp::min(lines_abs, height) as u16;
+        let (caps, capn) = if lines > 0 {
+            (&self.ind, &self.indn)
+        } else {
+            (&self.ri, &self.rin)
+        };
+        if !(caps.is_empty() && capn.is_empty()) {
+            if self.region != Some((top, bottom, size.rows)) {
+                let mut params = params!(top, bottom);
+                tparm(w, &self.csr, &mut params, &mut ::tinf::Vars::new())?;
+                self.region = Some((top, bottom, size.rows));
+                // Setting the scroll region may move the cursor.
+                motion.set_pos(None);
+                motion.set_region(Some((top, bottom)));
             }
-            Ok(init_ios)
+            let row = if lines > 0 { bottom } else { top };
+            motion.move_to(w, (0, row), Some(size))?;
+            Scroller::repeat(w, capn, caps, n)?;
+        } else if (!self.il.is_empty() || !self.il1.is_empty())
+            && (!self.dl.is_empty() || !self.dl1.is_empty())
+        {
+            // Deleting lines pulls up the rows below the region, so
+            // the same number of lines are inserted to push them
+            // back down (which only works for the whole screen).
+            self.reset(w, motion, Some(size))?;
+            let (del, ins) = if lines > 0 {
+                (top, bottom + 1 - n)
+            } else {
+                (bottom + 1 - n, top)
+            };
+            motion.move_to(w, (0, del), Some(size))?;
+            Scroller::repeat(w, &self.dl, &self.dl1, n)?;
+            motion.move_to(w, (0, ins), Some(size))?;
+            Scroller::repeat(w, &self.il, &self.il1, n)?;
+        } else {
+            return Ok(false);
         }
+        motion.forget_text();
+        Ok(true)
     }
 
-    fn init(&mut self) -> Result<()> {
-        if !self.is_tty_output() {
-            return Ok(());
+    fn repeat(w: &mut Write, capn: &[u8], cap1: &[u8], n: u16) -> Result<()> {
+        if !capn.is_empty() && (n > 1 || cap1.is_empty()) {
+            tparm(w, capn, &mut params!(n), &mut ::tinf::Vars::new())?;
+        } else {
+            for _ in 0..n {
+                w.write_all(cap1)?;
+            }
         }
-        self.stdout.write_all(&self.smcup)?;
         Ok(())
     }
 
-    fn uninit(&mut self) -> Result<()> {
-        if !::std::thread::panicking() {
-            self.stdout.write_all(&self.rmcup)?;
+    // Restore the full-screen scroll region, for the screen's current
+    // size if known.
+    fn reset(
+        &mut self,
+        w: &mut Write,
+        motion: &mut Motion,
+        size: Option<WinSize>,
+    ) -> Result<()> {
+        if let Some((_, _, rows)) = self.region.take() {
+            let rows = size.map_or(rows, |size| size.rows);
+            let mut params = params!(0, rows - 1);
+            tparm(w, &self.csr, &mut params, &mut ::tinf::Vars::new())?;
+            motion.set_pos(None);
+            motion.set_region(None);
         }
-        self.stdout.write_all(&self.cnorm)?;
         Ok(())
     }
+}
 
-    fn start_mouse_input(&mut self) -> Result<()> {
-        if !self.is_tty_input() {
-            return Ok(());
+// Changes to the palette and the dynamic colors, which are undone
+// when the terminal is dropped (or suspended, and then made again).
+#[derive(Default)]
+struct Palette {
+    initc: Vec<u8>,
+    oc: Vec<u8>,
+    // Whether the terminal understands OSC 4 and OSC 10-12.
+    osc: bool,
+    changed: bool,
+    dynamic: [bool; 3],
+    // The colors that were set.
+    colors: Vec<(u8, (u8, u8, u8))>,
+    dynamic_colors: [Option<(u8, u8, u8)>; 3],
+}
+
+impl Palette {
+    fn new(desc: &Desc) -> Palette {
+        let mut palette = Palette {
+            osc: is_xterm(desc),
+            ..Default::default()
+        };
+        // With hls, initc takes hue/lightness/saturation instead of
+        // RGB; and without oc, the palette can't be restored.
+        if desc[cap::ccc] && !desc[cap::hls] && !desc[cap::oc].is_empty() {
+            palette.initc = desc[cap::initc].to_vec();
+            palette.oc = desc[cap::oc].to_vec();
         }
-        self.stdout.write_all(b"\x1b[?1000h")?;
-        self.stdout.write_all(b"\x1b[?1003h")?;
-        if self.rxvt {
-            self.stdout.write_all(b"\x1b[?1015h")?;
+        palette
+    }
+
+    fn set(
+        &mut self,
+        w: &mut Write,
+        i: u8,
+        rgb: (u8, u8, u8),
+    ) -> Result<bool> {
+        if !self.initc.is_empty() {
+            // initc takes values from 0 to 1000.
+            let scale = |c: u8| (c as i32 * 1000 + 127) / 255;
+            let mut params =
+                params!(i, scale(rgb.0), scale(rgb.1), scale(rgb.2));
+            tparm(w, &self.initc, &mut params, &mut ::tinf::Vars::new())?;
+        } else if self.osc {
+            let (r, g, b) = rgb;
+            write!(w, "\x1b]4;{};rgb:{:02x}/{:02x}/{:02x}\x07", i, r, g, b)?;
+        } else {
+            return Ok(false);
+        }
+        self.changed = true;
+        self.colors.retain(|&(j, _)| j != i);
+        self.colors.push((i, rgb));
+        Ok(true)
+    }
+
+    fn set_dynamic(
+        &mut self,
+        w: &mut Write,
+        which: DynamicColor,
+        rgb: (u8, u8, u8),
+    ) -> Result<bool> {
+        if !self.osc {
+            return Ok(false);
+        }
+        let n = Palette::dynamic_index(which);
+        let (r, g, b) = rgb;
+        write!(w, "\x1b]{};rgb:{:02x}/{:02x}/{:02x}\x07", 10 + n, r, g, b)?;
+        self.dynamic[n] = true;
+        self.dynamic_colors[n] = Some(rgb);
+        Ok(true)
+    }
+
+    fn dynamic_index(which: DynamicColor) -> usize {
+        match which {
+            DynamicColor::Foreground => 0,
+            DynamicColor::Background => 1,
+            DynamicColor::Cursor => 2,
+        }
+    }
+
+    fn reset(&mut self, w: &mut Write) -> Result<()> {
+        if self.changed {
+            if self.initc.is_empty() {
+                w.write_all(b"\x1b]104\x07")?;
+            } else {
+                w.write_all(&self.oc)?;
+            }
+            self.changed = false;
+        }
+        for n in 0..3 {
+            if self.dynamic[n] {
+                write!(w, "\x1b]{}\x07", 110 + n)?;
+                self.dynamic[n] = false;
+            }
         }
-        self.stdout.write_all(b"\x1b[?1006h")?;
         Ok(())
     }
 
-    fn end_mouse_input(&mut self) -> Result<()> {
-        if !self.is_tty_input() {
-            return Ok(());
+    // Sets the colors again after a reset.
+    fn restore(&mut self, w: &mut Write) -> Result<()> {
+        for (i, rgb) in self.colors.clone() {
+            self.set(w, i, rgb)?;
         }
-        self.stdout.write_all(b"\x1b[?1000l")?;
-        self.stdout.write_all(b"\x1b[?1003l")?;
-        if self.rxvt {
-            self.stdout.write_all(b"\x1b[?1015l")?;
+        let which = [
+            DynamicColor::Foreground,
+            DynamicColor::Background,
+            DynamicColor::Cursor,
+        ];
+        for n in 0..3 {
+            if let Some(rgb) = self.dynamic_colors[n] {
+                self.set_dynamic(w, which[n], rgb)?;
+            }
         }
-        self.stdout.write_all(b"\x1b[?1006l")?;
         Ok(())
     }
 }
 
-impl<'a> Terminal for Term<'a> {
-    fn is_tty_input(&self) -> bool {
-        self.tmode.1 != TerminalMode::Redir
-    }
+// Changes to the cursor shape and color, which are undone when the
+// terminal is dropped (or suspended, and then made again).
+#[derive(Default)]
+struct Cursor {
+    ss: Vec<u8>,
+    se: Vec<u8>,
+    cs: Vec<u8>,
+    cr: Vec<u8>,
+    // Whether DECSCUSR can be used without Ss.
+    decscusr: bool,
+    shaped: bool,
+    colored: bool,
+    // The shape and color that were set (the color only if set with
+    // Cs, since otherwise the palette has it).
+    shape: Option<(CursorShape, bool)>,
+    color: Option<(u8, u8, u8)>,
+}
 
-    fn is_tty_output(&self) -> bool {
-        self.tmode.0 != TerminalMode::Redir
+impl Cursor {
+    fn new(desc: &Desc) -> Cursor {
+        let user = |name| desc.get_str_ext(&UserDef::named(name)).to_vec();
+        Cursor {
+            ss: user("Ss"),
+            se: user("Se"),
+            cs: user("Cs"),
+            cr: user("Cr"),
+            decscusr: is_xterm(desc),
+            ..Default::default()
+        }
     }
 
-    fn start_input(&mut self) -> Result<()> {
-        self.start_mouse_input()?;
-        self.stdout.flush()?;
-        ::input::start_threads(
-            self.tx.take().expect("start_input may only be called once"),
-        )
+    fn set_shape(
+        &mut self,
+        w: &mut Write,
+        shape: CursorShape,
+        blink: bool,
+    ) -> Result<bool> {
+        // The DECSCUSR parameter; odd values blink.
+        let n = match shape {
+            CursorShape::Block => 2,
+            CursorShape::Underline => 4,
+            CursorShape::Bar => 6,
+        };
+        let n = if blink { n - 1 } else { n };
+        if !self.ss.is_empty() {
+            tparm(w, &self.ss, &mut params!(n), &mut ::tinf::Vars::new())?;
+        } else if self.decscusr {
+            write!(w, "\x1b[{} q", n)?;
+        } else {
+            return Ok(false);
+        }
+        self.shaped = true;
+        self.shape = Some((shape, blink));
+        Ok(true)
     }
 
-    fn supported_styles(&self) -> Style {
-        self.styles.supported_styles()
+    fn set_color(
+        &mut self,
+        w: &mut Write,
+        rgb: (u8, u8, u8),
+        palette: &mut Palette,
+    ) -> Result<bool> {
+        if self.cs.is_empty() {
+            return palette.set_dynamic(w, DynamicColor::Cursor, rgb);
+        }
+        let (r, g, b) = rgb;
+        let color = format!("rgb:{:02x}/{:02x}/{:02x}", r, g, b);
+        tparm(w, &self.cs, &mut params!(color), &mut ::tinf::Vars::new())?;
+        self.colored = true;
+        self.color = Some(rgb);
+        Ok(true)
     }
 
-    fn max_colors(&self) -> (usize, usize, bool) {
-        self.styles.max_colors()
+    fn reset(&mut self, w: &mut Write) -> Result<()> {
+        if self.shaped {
+            if self.se.is_empty() {
+                w.write_all(b"\x1b[0 q")?;
+            } else {
+                w.write_all(&self.se)?;
+            }
+            self.shaped = false;
+        }
+        if self.colored {
+            if self.cr.is_empty() {
+                w.write_all(b"\x1b]112\x07")?;
+            } else {
+                w.write_all(&self.cr)?;
+            }
+            self.colored = false;
+        }
+        Ok(())
     }
 
-    fn get_size(&self) -> Result<WinSize> {
-        match get_size(Handle::Stdout) {
-            Some(ws) => Ok(ws),
-            None => Error::ffi_err("ioctl failed"),
+    // Sets the shape and color again after a reset.
+    fn restore(&mut self, w: &mut Write, palette: &mut Palette) -> Result<()> {
+        if let Some((shape, blink)) = self.shape {
+            self.set_shape(w, shape, blink)?;
         }
+        if let Some(rgb) = self.color {
+            self.set_color(w, rgb, palette)?;
+        }
+        Ok(())
     }
+}
 
-    fn get_style(&self) -> Style {
-        self.styles.style
-    }
+// Changes to the window title and icon name, which are undone (if
+// the terminal can save them) when the terminal is dropped (or
+// suspended, and then made again).
+#[derive(Default)]
+struct Title {
+    tsl: Vec<u8>,
+    fsl: Vec<u8>,
+    dsl: Vec<u8>,
+    // Whether the terminal understands OSC 1/2 and XTWINOPS.
+    osc: bool,
+    pushed: bool,
+    status: bool,
+    // The icon name and title that were set.
+    text: [Option<String>; 2],
+}
 
-    fn get_fg(&self) -> Color {
-        self.styles.fg
+impl Title {
+    fn new(desc: &Desc) -> Title {
+        let mut title = Title {
+            osc: is_xterm(desc),
+            ..Default::default()
+        };
+        if desc[cap::hs] {
+            title.tsl = desc[cap::tsl].to_vec();
+            title.fsl = desc[cap::fsl].to_vec();
+            title.dsl = desc[cap::dsl].to_vec();
+        }
+        title
     }
 
-    fn get_bg(&self) -> Color {
-        self.styles.bg
+    // Sets the title (OSC 2) or icon name (OSC 1).
+    fn set(&mut self, w: &mut Write, ps: u8, text: &str) -> Result<bool> {
+        // Control characters could end the sequence early.
+        let text: String = text.chars().filter(|c| !c.is_control()).collect();
+        // The status line is only used if the old title can't be
+        // saved with XTWINOPS.
+        if ps == 2 && !self.tsl.is_empty() && !self.osc {
+            tparm(w, &self.tsl, &mut params!(0), &mut ::tinf::Vars::new())?;
+            w.write_all(text.as_bytes())?;
+            w.write_all(&self.fsl)?;
+            self.status = true;
+        } else if self.osc {
+            if !self.pushed {
+                w.write_all(b"\x1b[22;0t")?;
+                self.pushed = true;
+            }
+            write!(w, "\x1b]{};{}\x07", ps, text)?;
+        } else {
+            return Ok(false);
+        }
+        self.text[ps as usize - 1] = Some(text);
+        Ok(true)
     }
 
-    fn set_style(&mut self, style: Style, fg: Color, bg: Color) -> Result<()> {
-        self.styles.sgr(&mut self.stdout, fg, bg, style)
+    fn reset(&mut self, w: &mut Write) -> Result<()> {
+        if self.pushed {
+            w.write_all(b"\x1b[23;0t")?;
+            self.pushed = false;
+        }
+        if self.status {
+            w.write_all(&self.dsl)?;
+            self.status = false;
+        }
+        Ok(())
     }
 
-    fn set_cursor(&mut self, coords: Coords) -> Result<()> {
-        if coords.0 > 32_767 || coords.1 > 32_767 {
-            panic!("coords out of range");
+    // Sets the icon name and title again after a reset.
+    fn restore(&mut self, w: &mut Write) -> Result<()> {
+        for ps in 1..3 {
+            if let Some(text) = self.text[ps as usize - 1].clone() {
+                self.set(w, ps, &text)?;
+            }
         }
-        tparm(
-            &mut self.stdout,
-            &self.cup,
-            &mut params!(coords.1, coords.0),
-            &mut ::tinf::Vars::new(),
-        )?;
         Ok(())
     }
+}
 
-    fn cursor_visible(&mut self, visible: bool) -> Result<()> {
-        // TODO: error if capability not present?
-        let cmd = if visible {
-            &self.cnorm
+// The rows drawn on in inline mode. Since their place on the screen
+// is unknown, they are addressed relative to the cursor, whose row
+// is remembered in case its position is lost.
+struct Inline {
+    rows: u16,
+    keep: bool,
+    cr: Vec<u8>,
+    ed: Vec<u8>,
+    el: Vec<u8>,
+    xenl: bool,
+    row: u16,
+    size: Option<WinSize>,
+    // The screen row of the first row, once a cursor position report
+    // has revealed it.
+    origin: Option<u16>,
+}
+
+impl Inline {
+    fn new(desc: &Desc, rows: u16, keep: bool) -> Inline {
+        let cr = if desc[cap::cr].is_empty() {
+            b"\r".to_vec()
         } else {
-            &self.civis
+            desc[cap::cr].to_vec()
         };
-        self.stdout.write_all(cmd)?;
-        Ok(())
+        Inline {
+            rows: ::std::cmp::max(rows, 1),
+            keep,
+            cr,
+            ed: desc[cap::ed].to_vec(),
+            el: desc[cap::el].to_vec(),
+            xenl: desc[cap::xenl],
+            row: 0,
+            size: None,
+            origin: None,
+        }
+    }
+
+    // The size of the rows, given the size of the terminal.
+    fn size(&self, size: Option<WinSize>) -> Option<WinSize> {
+        size.map(|size| WinSize {
+            rows: ::std::cmp::min(self.rows, size.rows),
+            ..size
+        })
     }
 
-    fn write(&mut self, text: &str) -> Result<()> {
-        write!(self.stdout, "{}", text)?;
+    // Make room for the rows at and below the cursor's line, by
+    // scrolling if necessary.
+    fn reserve(
+        &mut self,
+        w: &mut Write,
+        motion: &mut Motion,
+        size: Option<WinSize>,
+    ) -> Result<()> {
+        let rows = self.size(size).map_or(self.rows, |size| size.rows);
+        w.write_all(&self.cr)?;
+        for _ in 1..rows {
+            w.write_all(b"\n")?;
+        }
+        motion.set_pos(Some((0, rows - 1)));
+        motion.move_to(w, (0, 0), self.size(size))?;
+        self.size = size;
+        self.row = 0;
+        self.origin = None;
         Ok(())
     }
 
-    fn clear(&mut self) -> Result<()> {
-        self.stdout.write_all(&self.clear)?;
+    // Converts the screen position `pos` of the cursor to a position
+    // in the rows, or `None` if it is outside them. `cursor` is the
+    // cursor position in the rows, if known, which reveals where the
+    // rows are on the screen.
+    fn relative(
+        &mut self,
+        pos: Coords,
+        cursor: Option<Coords>,
+        size: Option<WinSize>,
+    ) -> Option<Coords> {
+        if let Some(cursor) = cursor {
+            self.origin = pos.1.checked_sub(cursor.1);
+        }
+        let origin = self.origin?;
+        let rows = self.size(size).map_or(self.rows, |size| size.rows);
+        if pos.1 < origin || pos.1 - origin >= rows {
+            return None;
+        }
+        Some((pos.0, pos.1 - origin))
+    }
+
+    // Recover the cursor position if it was lost by writing in the
+    // last column (which leaves the cursor there, or without xenl
+    // wraps it to the next line) or by a resize (after which it is
+    // assumed to be on the same row).
+    fn sync(
+        &mut self,
+        w: &mut Write,
+        motion: &mut Motion,
+        size: Option<WinSize>,
+    ) -> Result<()> {
+        let resized = size != self.size;
+        match motion.pos() {
+            Some(pos) if !resized => self.row = pos.1,
+            pos => {
+                let rows = self.size(size).map_or(self.rows, |size| size.rows);
+                let wrapped = pos.is_none() && !resized && !self.xenl;
+                let row = self.row + wrapped as u16;
+                w.write_all(&self.cr)?;
+                motion.set_pos(Some((0, ::std::cmp::min(row, rows - 1))));
+                if resized {
+                    self.origin = None;
+                }
+                self.size = size;
+            }
+        }
         Ok(())
     }
 
-    fn flush_output(&mut self) -> Result<()> {
-        self.stdout.flush()?;
+    fn clear(
+        &mut self,
+        w: &mut Write,
+        motion: &mut Motion,
+        size: Option<WinSize>,
+    ) -> Result<()> {
+        self.sync(w, motion, size)?;
+        let size = self.size(size);
+        if self.ed.is_empty() {
+            let rows = size.map_or(self.rows, |size| size.rows);
+            for row in 0..rows {
+                motion.move_to(w, (0, row), size)?;
+                w.write_all(&self.el)?;
+            }
+        }
+        motion.move_to(w, (0, 0), size)?;
+        w.write_all(&self.ed)?;
+        motion.forget_text();
+        self.row = 0;
         Ok(())
     }
 
-    #[cfg(debug_assertions)]
-    fn log(&mut self, text: &str) {
-        let mut stderr = io::stderr();
-        let _ = write!(stderr, "{}", text);
-        let _ = writeln!(stderr, "\r");
+    // Leave the cursor below the last row, or clear the rows.
+    fn finish(
+        &mut self,
+        w: &mut Write,
+        motion: &mut Motion,
+        size: Option<WinSize>,
+    ) -> Result<()> {
+        if !self.keep {
+            return self.clear(w, motion, size);
+        }
+        self.sync(w, motion, size)?;
+        let rows = self.size(size).map_or(self.rows, |size| size.rows);
+        motion.move_to(w, (0, rows - 1), self.size(size))?;
+        w.write_all(&self.cr)?;
+        w.write_all(b"\n")?;
+        motion.set_pos(None);
+        Ok(())
     }
 }
 
-impl<'a> Drop for Term<'a> {
-    fn drop(&mut self) {
-        let _ = self.end_mouse_input();
-        let _ = self.uninit();
-        let _ = self.styles.sgr0(&mut self.stdout);
-        let _ = self.stdout.flush();
-        unsafe {
-            libc::tcsetattr(0, 0, &self.init_ios);
+// Where output goes: stdout, the controlling terminal when stdout is
+// left for data, or a file descriptor owned by the caller.
+enum Output<'a> {
+    Stdout(io::StdoutLock<'a>),
+    Tty(io::BufWriter<fs::File>),
+    Fd(io::BufWriter<FdWriter>),
+}
+
+impl<'a> Write for Output<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Output::Stdout(ref mut w) => w.write(buf),
+            Output::Tty(ref mut w) => w.write(buf),
+            Output::Fd(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Output::Stdout(ref mut w) => w.flush(),
+            Output::Tty(ref mut w) => w.flush(),
+            Output::Fd(ref mut w) => w.flush(),
         }
     }
 }
 
-#[cfg(test)]
-#[allow(unused_must_use)]
-mod test {
-    use super::{BoldOrBright, Color, Style, Styles, UseTruecolor};
-    use tinf::Desc;
+// Writes to a file descriptor without closing it.
+struct FdWriter(libc::c_int);
 
-    fn sgr_desc() -> Desc {
-        use tinf::cap::*;
-        desc![
-            "sgr", "sgr",
-            sgr0 => "s",
+impl Write for FdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let ptr = buf.as_ptr() as *const libc::c_void;
+        let len = unsafe { libc::write(self.0, ptr, buf.len()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(len as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// How a terminal is connected.
+struct Io<'a> {
+    in_fd: libc::c_int,
+    out_fd: libc::c_int,
+    output: Output<'a>,
+    // Whether it is the process's own terminal, with signal handling
+    // and job control.
+    controlling: bool,
+    // The controlling terminal, if it was opened for input.
+    tty: Option<fs::File>,
+}
+
+// Opens the controlling terminal, for input and output.
+fn open_tty() -> Result<(fs::File, io::BufWriter<fs::File>)> {
+    let input = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    let output = input.try_clone()?;
+    Ok((input, io::BufWriter::new(output)))
+}
+
+pub(in term) struct Term<'a> {
+    styles: Styles,
+    inline: Option<Inline>,
+    title: Title,
+    palette: Palette,
+    cursor: Cursor,
+    motion: Motion,
+    scroller: Scroller,
+    smcup: Vec<u8>,
+    rmcup: Vec<u8>,
+    civis: Vec<u8>,
+    hidden: bool,
+    cnorm: Vec<u8>,
+    clear: Vec<u8>,
+    u7: Vec<u8>,
+    cpr_one_based: Option<bool>,
+    be: Vec<u8>,
+    bd: Vec<u8>,
+    keyboard: Option<KeyboardFlags>,
+    modify_other_keys: bool,
+    mouse_pixels: bool,
+    mouse_mode: MouseMode,
+    // Whether UTF-8 mouse mode (1005) is in effect (None until the
+    // terminal has been asked).
+    utf8_mouse: Option<bool>,
+    stdout: Output<'a>,
+    ambiguous: AmbiguousWidth,
+    wide_at_edge: WideAtEdge,
+    init_ios: libc::termios,
+    tx: Option<Sender<Box<Event>>>,
+    // Replies to queries are sent here by the reader, which sends
+    // input events to `tx`, or to `discard` if there is no `tx`.
+    replies: Receiver<Reply>,
+    // The reader of input, until the input thread takes it; queries
+    // read replies with it before then.
+    reader: Option<Reader>,
+    discard: Option<Receiver<Box<Event>>>,
+    // Reads input for `poll_event`, which takes events from `discard`.
+    events: Option<EventLoop>,
+    input_thread: Option<InputThread>,
+    shared: Arc<Shared>,
+    // Whether the input thread handles signals.
+    signals: bool,
+    input_started: bool,
+    // The size last read, kept while a resize would be signalled.
+    cached_size: Cell<Option<WinSize>>,
+    query_timeout: Duration,
+    tmode: (TerminalMode, TerminalMode),
+    // The terminal's input and output; stdin and stdout unless the
+    // controlling terminal was opened, or the caller's descriptors.
+    in_fd: libc::c_int,
+    out_fd: libc::c_int,
+    // The opened controlling terminal, closed (once input has stopped)
+    // when the terminal is dropped.
+    tty: Option<fs::File>,
+    rxvt: bool,
+}
+
+impl<'a> Term<'a> {
+    pub(in term) fn connect(opts: ConnectOptions) -> Result<Box<Terminal>> {
+        let ConnectOptions {
+            tx,
+            use_tc,
+            b_b,
+            screen,
+            signals,
+            tty,
+            desc,
+            fds,
+            ..
+        } = opts;
+        let desc = match desc {
+            Some(ref desc) => desc,
+            None => Desc::current(),
+        };
+        // Unlike the process's own terminal, any number of these can
+        // be connected at once.
+        if let Some((in_fd, out_fd)) = fds {
+            let io = Io {
+                in_fd,
+                out_fd,
+                output: Output::Fd(io::BufWriter::new(FdWriter(out_fd))),
+                controlling: false,
+                tty: None,
+            };
+            return Term::open(io, desc, tx, use_tc, b_b, screen, false);
+        }
+        if TERM.compare_and_swap(false, true, Ordering::SeqCst) {
+            panic!("TODO: better singleton panic message");
+        }
+        let io = if tty {
+            let (input, output) = open_tty()?;
+            Io {
+                in_fd: input.as_raw_fd(),
+                out_fd: output.get_ref().as_raw_fd(),
+                output: Output::Tty(output),
+                controlling: true,
+                tty: Some(input),
+            }
+        } else {
+            Io {
+                in_fd: 0,
+                out_fd: 1,
+                output: Output::Stdout(STDOUT.lock()),
+                controlling: true,
+                tty: None,
+            }
+        };
+        Term::open(io, desc, tx, use_tc, b_b, screen, signals)
+    }
+
+    fn open(
+        io: Io<'static>,
+        desc: &Desc,
+        tx: Option<Sender<Box<Event>>>,
+        use_tc: UseTruecolor,
+        b_b: BoldOrBright,
+        screen: Screen,
+        signals: bool,
+    ) -> Result<Box<Terminal>> {
+        let Io {
+            in_fd,
+            out_fd,
+            output,
+            controlling,
+            tty,
+        } = io;
+        let init_ios = Term::set_ios(in_fd)?;
+        let (reply_tx, replies) = channel();
+        let shared = Arc::new(Shared::new(in_fd, controlling));
+        let (reader_tx, discard) = match tx {
+            Some(ref tx) => (tx.clone(), None),
+            None => {
+                let (tx, rx) = channel();
+                (tx, Some(rx))
+            }
+        };
+        let reader =
+            Reader::new(desc, reader_tx, Some(reply_tx), shared.clone());
+        let inline = match screen {
+            Screen::Alternate => None,
+            Screen::Inline { rows, keep } => {
+                Some(Inline::new(desc, rows, keep))
+            }
+        };
+        let motion = match inline {
+            None => Motion::new(desc),
+            Some(_) => Motion::relative(desc),
+        };
+        let mut term = Term {
+            styles: Styles::new(desc, use_tc, b_b),
+            inline,
+            stdout: output,
+            motion,
+            scroller: Scroller::new(desc),
+            palette: Palette::new(desc),
+            cursor: Cursor::new(desc),
+            title: Title::new(desc),
+            smcup: desc[cap::smcup].to_vec(),
+            rmcup: desc[cap::rmcup].to_vec(),
+            civis: desc[cap::civis].to_vec(),
+            hidden: false,
+            cnorm: desc[cap::cnorm].to_vec(),
+            clear: desc[cap::clear].to_vec(),
+            u7: if desc[cap::u7].is_empty() {
+                b"\x1b[6n".to_vec()
+            } else {
+                desc[cap::u7].to_vec()
+            },
+            cpr_one_based: cpr_one_based(&desc[cap::u6]),
+            be: user_or(desc, "BE", b"\x1b[?2004h"),
+            bd: user_or(desc, "BD", b"\x1b[?2004l"),
+            keyboard: None,
+            modify_other_keys: false,
+            mouse_pixels: false,
+            mouse_mode: MouseMode::Any,
+            utf8_mouse: None,
+            tmode: (
+                TerminalMode::from_fd(out_fd),
+                TerminalMode::from_fd(in_fd),
+            ),
+            in_fd,
+            out_fd,
+            tty,
+            rxvt: is_rxvt(desc),
+            ambiguous: Default::default(),
+            wide_at_edge: Default::default(),
+            init_ios,
+            tx,
+            replies,
+            reader: Some(reader),
+            discard,
+            events: None,
+            input_thread: None,
+            shared,
+            signals,
+            input_started: false,
+            cached_size: Cell::new(None),
+            query_timeout: Duration::from_millis(100),
+        };
+        term.init()?;
+        Ok(Box::new(term))
+    }
+
+    fn set_ios(fd: libc::c_int) -> Result<libc::termios> {
+        unsafe {
+            let mut init_ios = ::std::mem::zeroed();
+            if 0 != libc::tcgetattr(fd, &mut init_ios) {
+                return Error::ffi_err("tcgetattr failed");
+            }
+            let mut ios = init_ios;
+            libc::cfmakeraw(&mut ios);
+            if 0 != libc::tcsetattr(fd, 0, &ios) {
+                return Error::ffi_err("tcsetattr failed");
+            }
+            Ok(init_ios)
+        }
+    }
+
+    fn init(&mut self) -> Result<()> {
+        if !self.is_tty_output() {
+            return Ok(());
+        }
+        match self.inline {
+            Some(ref mut inline) => {
+                let size = get_fd_size(self.out_fd);
+                inline.reserve(&mut self.stdout, &mut self.motion, size)?;
+            }
+            None => self.stdout.write_all(&self.smcup)?,
+        }
+        self.share_inline();
+        Ok(())
+    }
+
+    fn uninit(&mut self) -> Result<()> {
+        let tty = self.is_tty_output();
+        if let Some(ref mut inline) = self.inline {
+            if tty {
+                let size = get_fd_size(self.out_fd);
+                inline.finish(&mut self.stdout, &mut self.motion, size)?;
+            }
+        } else if !::std::thread::panicking() {
+            self.stdout.write_all(&self.rmcup)?;
+        }
+        self.stdout.write_all(&self.cnorm)?;
+        self.shared.set_inline_rows(None, 0);
+        Ok(())
+    }
+
+    // Tells the input parser where the inline rows are, so that mouse
+    // coordinates can be made relative to them.
+    fn share_inline(&self) {
+        match self.inline {
+            Some(ref inline) => {
+                let size = inline.size(self.term_size());
+                let rows = size.map_or(inline.rows, |size| size.rows);
+                self.shared.set_inline_rows(inline.origin, rows);
+            }
+            None => self.shared.set_inline_rows(None, 0),
+        }
+    }
+
+    // Sends `query`, and waits for a reply accepted by `want`,
+    // discarding any others (such as late replies to earlier
+    // queries).
+    fn query<T, F>(&mut self, query: &[u8], want: F) -> Result<Option<T>>
+    where
+        F: Fn(Reply) -> Option<T>,
+    {
+        if !self.is_tty_input() || !self.is_tty_output() {
+            return Ok(None);
+        }
+        self.stdout.write_all(query)?;
+        self.stdout.flush()?;
+        let deadline = Instant::now() + self.query_timeout;
+        loop {
+            while let Ok(reply) = self.replies.try_recv() {
+                if let Some(val) = want(reply) {
+                    return Ok(Some(val));
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            if let Some(ref mut events) = self.events {
+                match self.reader {
+                    Some(ref mut reader) => {
+                        events.step(reader, Some(deadline))?;
+                    }
+                    // There will be no more input.
+                    None => return Ok(None),
+                }
+            } else if self.input_started {
+                match self.replies.recv_timeout(deadline - now) {
+                    Ok(reply) => if let Some(val) = want(reply) {
+                        return Ok(Some(val));
+                    },
+                    Err(_) => return Ok(None),
+                }
+            } else {
+                if let Some(ref mut reader) = self.reader {
+                    input::read_inline(reader, deadline - now)?;
+                }
+                if let Some(ref discard) = self.discard {
+                    while discard.try_recv().is_ok() {}
+                }
+            }
+        }
+    }
+
+    fn start_polling(&mut self) -> Result<()> {
+        if self.input_started || self.tx.is_some() {
+            panic!(
+                "poll_event can't be used with an input channel, or after \
+                 start_input"
+            );
+        }
+        if self.signals {
+            input::start_signals()?;
+        }
+        self.events = Some(EventLoop::new(self.signals));
+        self.input_started = true;
+        // Input read while detecting mouse modes is kept as events.
+        self.start_mouse_input()?;
+        self.start_paste_input()?;
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    // Reads input on this thread until there is an input event, or
+    // until `deadline`.
+    fn next_event(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<InputEvent>> {
+        if self.events.is_none() {
+            self.start_polling()?;
+        }
+        loop {
+            if let Some(evt) = self.take_event()? {
+                return Ok(Some(evt));
+            }
+            if deadline.map_or(false, |deadline| deadline <= Instant::now()) {
+                return Ok(None);
+            }
+            let more = match (&mut self.events, &mut self.reader) {
+                (&mut Some(ref mut events), &mut Some(ref mut reader)) => {
+                    events.step(reader, deadline)?
+                }
+                _ => unreachable!(),
+            };
+            if !more {
+                // Dropping the reader disconnects `discard` once the
+                // events already parsed have been taken.
+                self.reader = None;
+            }
+        }
+    }
+
+    // The next input event already parsed, if any.
+    fn take_event(&mut self) -> Result<Option<InputEvent>> {
+        let discard = match self.discard {
+            Some(ref discard) => discard,
+            None => return Ok(None),
+        };
+        loop {
+            match discard.try_recv() {
+                Ok(evt) => if let Some(evt) = evt.as_any().downcast_ref() {
+                    return Ok(Some(InputEvent::clone(evt)));
+                },
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => {
+                    let eof = io::ErrorKind::UnexpectedEof;
+                    return Err(io::Error::new(eof, "end of input").into());
+                }
+            }
+        }
+    }
+
+    fn start_mouse_input(&mut self) -> Result<()> {
+        if !self.is_tty_input() {
+            return Ok(());
+        }
+        self.mouse_tracking(true)?;
+        self.detect_mouse()?;
+        self.stdout.write_all(b"\x1b[?1004h")?;
+        Ok(())
+    }
+
+    // Finds out what is needed to decode mouse reports, once tracking
+    // has been enabled.
+    fn detect_mouse(&mut self) -> Result<()> {
+        if self.mouse_mode == MouseMode::None {
+            return Ok(());
+        }
+        if self.utf8_mouse.is_none() {
+            self.detect_utf8_mouse()?;
+        }
+        // Mouse events are dropped until the inline rows are found.
+        if self.inline.as_ref().map_or(false, |i| i.origin.is_none()) {
+            self.query_cursor()?;
+        }
+        Ok(())
+    }
+
+    // Asks whether UTF-8 mouse mode took effect, since otherwise
+    // coordinates above 95 are single bytes that can look like UTF-8.
+    fn detect_utf8_mouse(&mut self) -> Result<()> {
+        let status = Cell::new(None);
+        self.shared.expect_mode_report(true);
+        let reply = self.query(b"\x1b[?1005$p\x1b[c", |reply| match reply {
+            Reply::Mode(1005, st) => {
+                status.set(Some(st));
+                None
+            }
+            Reply::Attributes => Some(()),
+            _ => None,
+        });
+        self.shared.expect_mode_report(false);
+        reply?;
+        let enabled = match status.get() {
+            Some(1) | Some(3) => true,
+            _ => false,
+        };
+        if !enabled {
+            self.stdout.write_all(b"\x1b[?1005l")?;
+        }
+        self.utf8_mouse = Some(enabled);
+        self.shared.expect_utf8_mouse(enabled);
+        Ok(())
+    }
+
+    // Enables or disables reporting for the current mouse mode, and
+    // every encoding the terminal might support (terminals use the
+    // last one enabled that they know, except that SGR takes
+    // precedence).
+    fn mouse_tracking(&mut self, on: bool) -> Result<()> {
+        let tracking: &[u16] = match self.mouse_mode {
+            MouseMode::None => return Ok(()),
+            MouseMode::Press => &[1000],
+            MouseMode::Drag => &[1000, 1002],
+            MouseMode::Any => &[1000, 1003],
+        };
+        let mut modes = tracking.to_vec();
+        if self.utf8_mouse != Some(false) {
+            modes.push(1005);
+        }
+        if self.rxvt {
+            modes.push(1015);
+        }
+        modes.push(1006);
+        if self.mouse_pixels {
+            modes.push(1016);
+        }
+        let fin = if on { 'h' } else { 'l' };
+        for mode in modes {
+            write!(self.stdout, "\x1b[?{}{}", mode, fin)?;
+        }
+        Ok(())
+    }
+
+    // The size of the terminal. Once signals are being handled, it is
+    // only read again after SIGWINCH.
+    fn term_size(&self) -> Option<WinSize> {
+        let cache = self.signals && self.input_started;
+        if cache && !self.shared.take_resized() {
+            if let Some(size) = self.cached_size.get() {
+                return Some(size);
+            }
+        }
+        let size = get_fd_size(self.out_fd);
+        if cache {
+            self.cached_size.set(size);
+        }
+        size
+    }
+
+    // The size of the terminal, or in inline mode of the rows being
+    // drawn on.
+    fn size(&self) -> Option<WinSize> {
+        let size = self.term_size();
+        match self.inline {
+            Some(ref inline) => inline.size(size),
+            None => size,
+        }
+    }
+
+    // Write text that has no control characters.
+    fn output(&mut self, text: &str, cols: usize) -> Result<u16> {
+        if let (Some(inline), Some(pos)) =
+            (self.inline.as_mut(), self.motion.pos())
+        {
+            inline.row = pos.1;
+        }
+        self.stdout.write_all(text.as_bytes())?;
+        if self.motion.pos().is_some() {
+            let size = self.size();
+            self.motion.advance(text, cols, size);
+        }
+        Ok(::std::cmp::min(cols, 0xffff) as u16)
+    }
+
+    fn end_mouse_input(&mut self) -> Result<()> {
+        if !self.is_tty_input() {
+            return Ok(());
+        }
+        self.mouse_tracking(false)?;
+        self.stdout.write_all(b"\x1b[?1004l")?;
+        Ok(())
+    }
+
+    fn start_paste_input(&mut self) -> Result<()> {
+        if !self.is_tty_input() {
+            return Ok(());
+        }
+        self.stdout.write_all(&self.be)?;
+        Ok(())
+    }
+
+    fn end_paste_input(&mut self) -> Result<()> {
+        if !self.is_tty_input() {
+            return Ok(());
+        }
+        self.stdout.write_all(&self.bd)?;
+        Ok(())
+    }
+
+    // The kitty keyboard protocol keeps a stack of flags for each
+    // screen, so they are pushed after entering the alternate screen
+    // and popped before leaving it.
+    fn push_keyboard_flags(&mut self) -> Result<()> {
+        if let Some(flags) = self.keyboard {
+            write!(self.stdout, "\x1b[>{}u", flags.bits())?;
+        }
+        Ok(())
+    }
+
+    fn pop_keyboard_flags(&mut self) -> Result<()> {
+        if self.keyboard.is_some() {
+            self.stdout.write_all(b"\x1b[<u")?;
+        }
+        Ok(())
+    }
+
+    fn start_modify_other_keys(&mut self) -> Result<()> {
+        if self.modify_other_keys {
+            self.stdout.write_all(b"\x1b[>4;2m")?;
+        }
+        Ok(())
+    }
+
+    fn end_modify_other_keys(&mut self) -> Result<()> {
+        if self.modify_other_keys {
+            self.stdout.write_all(b"\x1b[>4m")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Terminal for Term<'a> {
+    fn is_tty_input(&self) -> bool {
+        self.tmode.1 != TerminalMode::Redir
+    }
+
+    fn is_tty_output(&self) -> bool {
+        self.tmode.0 != TerminalMode::Redir
+    }
+
+    fn start_input(&mut self) -> Result<()> {
+        let reader = match (self.tx.take(), self.reader.take()) {
+            (Some(_), Some(reader)) => reader,
+            _ => panic!("start_input may only be called once"),
+        };
+        self.input_started = true;
+        self.input_thread = Some(input::start_threads(reader, self.signals)?);
+        // Input read while detecting mouse modes is sent as events.
+        self.start_mouse_input()?;
+        self.start_paste_input()?;
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    fn poll_event(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<InputEvent>> {
+        self.next_event(Some(Instant::now() + timeout))
+    }
+
+    fn read_event(&mut self) -> Result<InputEvent> {
+        loop {
+            if let Some(evt) = self.next_event(None)? {
+                return Ok(evt);
+            }
+        }
+    }
+
+    fn supported_styles(&self) -> Style {
+        self.styles.supported_styles()
+    }
+
+    fn max_colors(&self) -> (usize, usize, bool) {
+        self.styles.max_colors()
+    }
+
+    fn get_size(&self) -> Result<WinSize> {
+        self.cached_size.set(None);
+        match self.size() {
+            Some(ws) => Ok(ws),
+            None => Error::ffi_err("ioctl failed"),
+        }
+    }
+
+    fn get_style(&self) -> Style {
+        self.styles.style
+    }
+
+    fn get_fg(&self) -> Color {
+        self.styles.fg
+    }
+
+    fn get_bg(&self) -> Color {
+        self.styles.bg
+    }
+
+    fn set_underline_color(&mut self, color: Color) -> Result<()> {
+        let prev = self.styles.ul_color;
+        self.styles.set_ul_color(&mut self.stdout, color)?;
+        if prev != color {
+            self.motion.style_changed();
+        }
+        Ok(())
+    }
+
+    fn set_style(&mut self, style: Style, fg: Color, bg: Color) -> Result<()> {
+        let prev = (self.styles.style, self.styles.fg, self.styles.bg);
+        self.styles.sgr(&mut self.stdout, fg, bg, style)?;
+        if prev != (self.styles.style, self.styles.fg, self.styles.bg) {
+            self.motion.style_changed();
+        }
+        Ok(())
+    }
+
+    fn set_color_mapping(&mut self, mapping: ColorMapping) {
+        self.styles.mapping = mapping;
+    }
+
+    fn set_palette(&mut self, index: u8, rgb: (u8, u8, u8)) -> Result<bool> {
+        self.palette.set(&mut self.stdout, index, rgb)
+    }
+
+    fn set_dynamic_color(
+        &mut self,
+        which: DynamicColor,
+        rgb: (u8, u8, u8),
+    ) -> Result<bool> {
+        self.palette.set_dynamic(&mut self.stdout, which, rgb)
+    }
+
+    fn set_query_timeout(&mut self, timeout: Duration) {
+        self.query_timeout = timeout;
+    }
+
+    fn query_palette(&mut self, index: u8) -> Result<Option<(u8, u8, u8)>> {
+        if !self.palette.osc {
+            return Ok(None);
+        }
+        let query = format!("\x1b]4;{};?\x07", index);
+        self.query(query.as_bytes(), |reply| match reply {
+            Reply::Palette(i, rgb) if i == index => Some(rgb),
+            _ => None,
+        })
+    }
+
+    fn query_dynamic_color(
+        &mut self,
+        which: DynamicColor,
+    ) -> Result<Option<(u8, u8, u8)>> {
+        if !self.palette.osc {
+            return Ok(None);
+        }
+        let n = Palette::dynamic_index(which);
+        let query = format!("\x1b]{};?\x07", 10 + n);
+        self.query(query.as_bytes(), |reply| match reply {
+            Reply::Dynamic(i, rgb) if i as usize == n => Some(rgb),
+            _ => None,
+        })
+    }
+
+    fn query_cursor(&mut self) -> Result<Option<Coords>> {
+        let base = match self.cpr_one_based {
+            Some(true) => 1,
+            Some(false) => 0,
+            None => return Ok(None),
+        };
+        let query = self.u7.clone();
+        self.shared.expect_cursor_report(true);
+        let pos = self.query(&query, |reply| match reply {
+            Reply::Cursor(row, col) => Some((row, col)),
+            _ => None,
+        });
+        self.shared.expect_cursor_report(false);
+        let pos = pos?.map(|(row, col)| {
+            (col.saturating_sub(base), row.saturating_sub(base))
+        });
+        // In inline mode, positions are relative to the rows.
+        let size = self.term_size();
+        let pos = match (pos, self.inline.as_mut()) {
+            (Some(pos), Some(inline)) => {
+                inline.relative(pos, self.motion.pos(), size)
+            }
+            (pos, _) => pos,
+        };
+        self.share_inline();
+        Ok(pos)
+    }
+
+    fn set_keyboard_flags(&mut self, flags: KeyboardFlags) -> Result<bool> {
+        if self.keyboard.is_some() {
+            write!(self.stdout, "\x1b[={};1u", flags.bits())?;
+            self.keyboard = Some(flags);
+            return Ok(true);
+        }
+        // Every terminal replies to the device attributes query, so an
+        // unsupported protocol is detected without waiting.
+        self.shared.expect_extended_keys(true);
+        let reply = self.query(b"\x1b[?u\x1b[c", |reply| match reply {
+            Reply::Keyboard(_) => Some(true),
+            Reply::Attributes => Some(false),
+            _ => None,
+        });
+        if let Ok(Some(true)) = reply {
+            self.keyboard = Some(flags);
+            self.push_keyboard_flags()?;
+            return Ok(true);
+        }
+        // Reports from modifyOtherKeys still need decoding.
+        self.shared.expect_extended_keys(self.modify_other_keys);
+        reply.map(|_| false)
+    }
+
+    fn enable_modify_other_keys(&mut self) -> Result<bool> {
+        if !self.is_tty_input() || !self.is_tty_output() {
+            return Ok(false);
+        }
+        if !self.modify_other_keys {
+            self.modify_other_keys = true;
+            self.shared.expect_extended_keys(true);
+            self.start_modify_other_keys()?;
+        }
+        Ok(true)
+    }
+
+    fn set_mouse_pixels(&mut self, enabled: bool) -> Result<bool> {
+        if !self.is_tty_input() || !self.is_tty_output() {
+            return Ok(false);
+        }
+        if enabled && self.shared.cell_size().is_none() {
+            return Ok(false);
+        }
+        if enabled != self.mouse_pixels {
+            if self.input_started {
+                self.mouse_tracking(false)?;
+            }
+            self.mouse_pixels = enabled;
+            self.shared.expect_pixel_mouse(enabled);
+            if self.input_started {
+                self.mouse_tracking(true)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn set_mouse_mode(&mut self, mode: MouseMode) -> Result<()> {
+        if !self.is_tty_input() || mode == self.mouse_mode {
+            self.mouse_mode = mode;
+            return Ok(());
+        }
+        if self.input_started {
+            self.mouse_tracking(false)?;
+        }
+        self.mouse_mode = mode;
+        if self.input_started {
+            self.mouse_tracking(true)?;
+            self.detect_mouse()?;
+        }
+        Ok(())
+    }
+
+    fn set_esc_timeout(&mut self, timeout: EscTimeout) {
+        self.shared.set_esc_timeout(timeout);
+    }
+
+    fn set_esc_policy(&mut self, policy: EscPolicy) {
+        self.shared.set_esc_policy(policy);
+    }
+
+    fn is_dark_background(&mut self) -> Result<Option<bool>> {
+        let bg = self.query_dynamic_color(DynamicColor::Background)?;
+        if let Some(rgb) = bg {
+            return Ok(Some(color::is_dark(rgb)));
+        }
+        Ok(env::var("COLORFGBG")
+            .ok()
+            .and_then(|val| color::colorfgbg_is_dark(&val)))
+    }
+
+    fn set_cursor(&mut self, coords: Coords) -> Result<()> {
+        if coords.0 > 32_767 || coords.1 > 32_767 {
+            panic!("coords out of range");
+        }
+        let size = self.term_size();
+        if let Some(ref mut inline) = self.inline {
+            inline.sync(&mut self.stdout, &mut self.motion, size)?;
+            if coords.1 >= inline.size(size).map_or(0, |size| size.rows) {
+                return Ok(());
+            }
+        }
+        let size = self.size();
+        self.motion.move_to(&mut self.stdout, coords, size)
+    }
+
+    fn scroll(&mut self, top: u16, bottom: u16, lines: i32) -> Result<bool> {
+        // Scroll regions need to know where the rows are.
+        if self.inline.is_some() {
+            return Ok(false);
+        }
+        let size = match self.term_size() {
+            Some(size) => size,
+            None => return Ok(false),
+        };
+        self.scroller.scroll(
+            &mut self.stdout,
+            &mut self.motion,
+            (top, bottom),
+            lines,
+            size,
+        )
+    }
+
+    fn cursor_visible(&mut self, visible: bool) -> Result<()> {
+        // TODO: error if capability not present?
+        self.hidden = !visible;
+        let cmd = if visible {
+            &self.cnorm
+        } else {
+            &self.civis
+        };
+        self.stdout.write_all(cmd)?;
+        Ok(())
+    }
+
+    fn set_cursor_shape(
+        &mut self,
+        shape: CursorShape,
+        blink: bool,
+    ) -> Result<bool> {
+        self.cursor.set_shape(&mut self.stdout, shape, blink)
+    }
+
+    fn set_cursor_color(&mut self, rgb: (u8, u8, u8)) -> Result<bool> {
+        let palette = &mut self.palette;
+        self.cursor.set_color(&mut self.stdout, rgb, palette)
+    }
+
+    fn write(&mut self, text: &str) -> Result<u16> {
+        let text = width::visible(text);
+        let cols = width::width(&text, self.ambiguous);
+        self.output(&text, cols)
+    }
+
+    fn write_clipped(&mut self, text: &str, max_cols: u16) -> Result<u16> {
+        let (text, cols) = width::clip(
+            text,
+            max_cols as usize,
+            self.ambiguous,
+            self.wide_at_edge,
+        );
+        self.output(&text, cols)
+    }
+
+    fn set_width_policy(
+        &mut self,
+        ambiguous: AmbiguousWidth,
+        edge: WideAtEdge,
+    ) {
+        self.ambiguous = ambiguous;
+        self.wide_at_edge = edge;
+    }
+
+    fn set_title(&mut self, title: &str) -> Result<bool> {
+        self.title.set(&mut self.stdout, 2, title)
+    }
+
+    fn set_icon_name(&mut self, name: &str) -> Result<bool> {
+        self.title.set(&mut self.stdout, 1, name)
+    }
+
+    fn set_job_control(&mut self, enabled: bool) {
+        self.shared.set_job_control(enabled);
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        if !self.shared.is_controlling() {
+            return Ok(());
+        }
+        if self.input_started {
+            self.end_mouse_input()?;
+            self.end_paste_input()?;
+        }
+        let size = get_fd_size(self.out_fd);
+        self.scroller.reset(&mut self.stdout, &mut self.motion, size)?;
+        self.palette.reset(&mut self.stdout)?;
+        self.cursor.reset(&mut self.stdout)?;
+        self.title.reset(&mut self.stdout)?;
+        self.pop_keyboard_flags()?;
+        self.end_modify_other_keys()?;
+        self.uninit()?;
+        self.styles.sgr0(&mut self.stdout)?;
+        self.stdout.flush()?;
+        unsafe {
+            libc::tcsetattr(self.in_fd, 0, &self.init_ios);
+        }
+        self.shared.stop_process();
+        self.init_ios = Term::set_ios(self.in_fd)?;
+        self.motion.set_pos(None);
+        self.init()?;
+        self.palette.restore(&mut self.stdout)?;
+        self.cursor.restore(&mut self.stdout, &mut self.palette)?;
+        self.title.restore(&mut self.stdout)?;
+        self.push_keyboard_flags()?;
+        self.start_modify_other_keys()?;
+        if self.hidden {
+            self.stdout.write_all(&self.civis)?;
+        }
+        if self.input_started {
+            self.start_mouse_input()?;
+            self.start_paste_input()?;
+        }
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        let size = self.term_size();
+        if let Some(ref mut inline) = self.inline {
+            return inline.clear(&mut self.stdout, &mut self.motion, size);
+        }
+        self.stdout.write_all(&self.clear)?;
+        // The clear capability also homes the cursor.
+        self.motion.forget_text();
+        if self.clear.is_empty() {
+            self.motion.set_pos(None);
+        } else {
+            self.motion.set_pos(Some((0, 0)));
+        }
+        Ok(())
+    }
+
+    fn flush_output(&mut self) -> Result<()> {
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    #[cfg(debug_assertions)]
+    fn log(&mut self, text: &str) {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "{}", text);
+        let _ = writeln!(stderr, "\r");
+    }
+}
+
+// The user-defined capability `name`, or `default` if it is absent.
+fn user_or(desc: &Desc, name: &str, default: &[u8]) -> Vec<u8> {
+    let cap = desc.get_str_ext(&UserDef::named(name));
+    if cap.is_empty() {
+        default.to_vec()
+    } else {
+        cap.to_vec()
+    }
+}
+
+// Whether the terminal's cursor position reports (u6) are one-based,
+// or None if they are not in the usual "CSI row;col R" form.
+fn cpr_one_based(u6: &[u8]) -> Option<bool> {
+    if u6.is_empty() || u6 == b"\x1b[%i%d;%dR" {
+        Some(true)
+    } else if u6 == b"\x1b[%d;%dR" {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+impl<'a> Drop for Term<'a> {
+    fn drop(&mut self) {
+        // Stop reading input first, since the caller may close the
+        // terminal's file descriptors once it is dropped.
+        self.input_thread = None;
+        // In the background the shell owns the terminal (and changing
+        // it would stop the process).
+        if !self.shared.is_foreground() {
+            return;
+        }
+        let _ = self.end_mouse_input();
+        self.shared.expect_pixel_mouse(false);
+        if self.input_started {
+            let _ = self.end_paste_input();
+        }
+        let size = get_fd_size(self.out_fd);
+        let _ = self.scroller.reset(&mut self.stdout, &mut self.motion, size);
+        let _ = self.palette.reset(&mut self.stdout);
+        let _ = self.cursor.reset(&mut self.stdout);
+        let _ = self.title.reset(&mut self.stdout);
+        let _ = self.pop_keyboard_flags();
+        let _ = self.end_modify_other_keys();
+        self.shared.expect_extended_keys(false);
+        let _ = self.uninit();
+        let _ = self.styles.sgr0(&mut self.stdout);
+        let _ = self.stdout.flush();
+        unsafe {
+            libc::tcsetattr(self.in_fd, 0, &self.init_ios);
+        }
+        drop(self.tty.take());
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_must_use)]
+mod test {
+    use super::{BoldOrBright, Color, Cursor, CursorShape, DynamicColor,
+                Inline, Palette, Scroller, Style, Styles, Title,
+                UseTruecolor};
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+    use std::time::Duration;
+    use libc;
+    use input::{InputEvent, Key, Mods};
+    use term::{downsample, ConnectOptions, KeyboardFlags, MouseMode, Terminal,
+               WinSize};
+    use term::motion::Motion;
+    use tinf::Desc;
+
+    fn sgr_desc() -> Desc {
+        use tinf::cap::*;
+        desc![
+            "sgr", "sgr",
+            sgr0 => "s",
             bold => "B",
             sitm => "I",
             ritm => "i",
@@ -541,6 +2181,27 @@ mod test {
         assert_eq!(b"c(2)".to_vec(), w);
     }
 
+    #[test]
+    fn downsampled_colors() {
+        let mut styles =
+            Styles::new(&sgr_desc(), UseTruecolor::Never, BoldOrBright::Bold);
+        let mut w: Vec<u8> = Vec::new();
+        let red = Color::TrueColor(255, 0, 0);
+        let blue = Color::TrueColor(0, 0, 250);
+        styles.sgr(&mut w, red, blue, Style::empty());
+        assert_eq!(b"196(21)".to_vec(), w);
+        assert_eq!(Color::Palette(196), styles.fg);
+
+        let mut styles = Styles::new(
+            &bright_desc(),
+            UseTruecolor::Never,
+            BoldOrBright::Bold,
+        );
+        w.clear();
+        styles.sgr(&mut w, red, Color::Palette(12), Style::empty());
+        assert_eq!(b"1(4)".to_vec(), w);
+    }
+
     fn bright_desc() -> Desc {
         use tinf::cap::*;
         desc![
@@ -586,4 +2247,611 @@ mod test {
         styles.sgr(&mut w, Color::Default, Color::Default, Style::empty());
         assert_eq!(b"s".to_vec(), w);
     }
+
+    fn scroll_desc(with_csr: bool) -> Desc {
+        use tinf::cap::*;
+        let mut desc = desc![
+            "scroll", "scroll",
+            cup => "<%p1%d,%p2%d>",
+            ind => "I",
+            indn => "[I%p1%d]",
+            ri => "R",
+            il1 => "L",
+            dl1 => "D",
+        ];
+        if with_csr {
+            desc = desc![
+                "scroll", "scroll",
+                cup => "<%p1%d,%p2%d>",
+                csr => "{%p1%d-%p2%d}",
+                ind => "I",
+                indn => "[I%p1%d]",
+                ri => "R",
+            ];
+        }
+        desc
+    }
+
+    fn scroll(
+        scroller: &mut Scroller,
+        motion: &mut Motion,
+        rows: (u16, u16),
+        lines: i32,
+    ) -> (bool, Vec<u8>) {
+        let size = WinSize { cols: 80, rows: 24 };
+        let mut w: Vec<u8> = Vec::new();
+        let ok = scroller.scroll(&mut w, motion, rows, lines, size).unwrap();
+        (ok, w)
+    }
+
+    #[test]
+    fn scroll_region() {
+        let desc = scroll_desc(true);
+        let mut scroller = Scroller::new(&desc);
+        let mut motion = Motion::new(&desc);
+        let (ok, w) = scroll(&mut scroller, &mut motion, (2, 10), 1);
+        assert!(ok);
+        assert_eq!(b"{2-10}<10,0>I".to_vec(), w);
+        let (_, w) = scroll(&mut scroller, &mut motion, (2, 10), 3);
+        assert_eq!(b"[I3]".to_vec(), w);
+        let (_, w) = scroll(&mut scroller, &mut motion, (2, 10), -2);
+        assert_eq!(b"<2,0>RR".to_vec(), w);
+
+        let mut w: Vec<u8> = Vec::new();
+        scroller.reset(&mut w, &mut motion, None);
+        assert_eq!(b"{0-23}".to_vec(), w);
+
+        // After a resize, the whole of the new screen is restored.
+        scroll(&mut scroller, &mut motion, (2, 10), 1);
+        let mut w: Vec<u8> = Vec::new();
+        let size = WinSize { cols: 80, rows: 30 };
+        scroller.reset(&mut w, &mut motion, Some(size));
+        assert_eq!(b"{0-29}".to_vec(), w);
+    }
+
+    #[test]
+    fn scroll_lines() {
+        let desc = scroll_desc(false);
+        let mut scroller = Scroller::new(&desc);
+        let mut motion = Motion::new(&desc);
+        let (ok, w) = scroll(&mut scroller, &mut motion, (2, 10), 2);
+        assert!(ok);
+        assert_eq!(b"<2,0>DD<9,0>LL".to_vec(), w);
+        let (_, w) = scroll(&mut scroller, &mut motion, (2, 10), -1);
+        assert_eq!(b"<10,0>D<2,0>L".to_vec(), w);
+
+        let (ok, _) = scroll(&mut scroller, &mut motion, (2, 30), 1);
+        assert!(!ok);
+
+        let lines = i32::min_value();
+        let (ok, w) = scroll(&mut scroller, &mut motion, (2, 3), lines);
+        assert!(ok);
+        assert_eq!(b"DDLL".to_vec(), w);
+    }
+
+    #[test]
+    fn scroll_mixed() {
+        let desc = {
+            use tinf::cap::*;
+            desc![
+                "scroll", "scroll",
+                cup => "<%p1%d,%p2%d>",
+                csr => "{%p1%d-%p2%d}",
+                ind => "I",
+                il1 => "L",
+                dl1 => "D",
+            ]
+        };
+        let mut scroller = Scroller::new(&desc);
+        let mut motion = Motion::new(&desc);
+        let (_, w) = scroll(&mut scroller, &mut motion, (2, 10), 1);
+        assert_eq!(b"{2-10}<10,0>I".to_vec(), w);
+        // Without ri, the scroll region is reset to insert lines.
+        let (ok, w) = scroll(&mut scroller, &mut motion, (2, 10), -1);
+        assert!(ok);
+        assert_eq!(b"{0-23}<10,0>D<2,0>L".to_vec(), w);
+    }
+
+    fn attr_desc() -> Desc {
+        use tinf::cap::*;
+        desc![
+            "attr", "attr",
+            sgr0 => "s",
+            smul => "U",
+            rmul => "u",
+            rev => "R",
+            smso => "S",
+            rmso => "z",
+            blink => "K",
+            dim => "D",
+            invis => "V",
+            op => "c",
+            colors => 8,
+            ncv => 0x08,
+            UserDef::named("smxx") => "X",
+            UserDef::named("rmxx") => "x",
+            UserDef::named("Smulx") => "<U%p1%d>",
+            UserDef::named("Setulc") => "{%p1%d}",
+        ]
+    }
+
+    #[test]
+    fn extended_attrs() {
+        let mut styles =
+            Styles::new(&attr_desc(), UseTruecolor::Auto, BoldOrBright::Bold);
+        let expected = Style::UNDERLINE | Style::DOUBLE_UNDERLINE
+            | Style::CURLY_UNDERLINE
+            | Style::DOTTED_UNDERLINE | Style::REVERSE
+            | Style::DIM | Style::INVISIBLE | Style::STANDOUT
+            | Style::STRIKETHROUGH;
+        // ncv disables blink.
+        assert_eq!(expected, styles.supported_styles());
+
+        let mut w: Vec<u8> = Vec::new();
+        styles.sgr(
+            &mut w,
+            Color::Default,
+            Color::Default,
+            Style::REVERSE | Style::STANDOUT | Style::STRIKETHROUGH,
+        );
+        assert_eq!(b"RSX".to_vec(), w);
+
+        w.clear();
+        let style = Style::REVERSE | Style::STANDOUT;
+        styles.sgr(&mut w, Color::Default, Color::Default, style);
+        assert_eq!(b"x".to_vec(), w);
+
+        w.clear();
+        styles.sgr(&mut w, Color::Default, Color::Default, Style::REVERSE);
+        assert_eq!(b"z".to_vec(), w);
+
+        w.clear();
+        styles.sgr(&mut w, Color::Default, Color::Default, Style::empty());
+        assert_eq!(b"s".to_vec(), w);
+
+        w.clear();
+        let style = Style::CURLY_UNDERLINE;
+        styles.sgr(&mut w, Color::Default, Color::Default, style);
+        assert_eq!(b"<U3>".to_vec(), w);
+
+        w.clear();
+        styles.sgr(&mut w, Color::Default, Color::Default, Style::UNDERLINE);
+        assert_eq!(b"U".to_vec(), w);
+
+        w.clear();
+        let style = Style::UNDERLINE | Style::STANDOUT;
+        styles.sgr(&mut w, Color::Default, Color::Default, style);
+        styles.sgr(&mut w, Color::Default, Color::Default, Style::STANDOUT);
+        assert_eq!(b"Su".to_vec(), w);
+    }
+
+    #[test]
+    fn underline_color() {
+        let mut styles =
+            Styles::new(&attr_desc(), UseTruecolor::Auto, BoldOrBright::Bold);
+        let mut w: Vec<u8> = Vec::new();
+        styles.set_ul_color(&mut w, Color::TrueColor(0, 1, 2));
+        assert_eq!(b"{258}".to_vec(), w);
+
+        // Restored after a reset.
+        w.clear();
+        let style = Style::UNDERLINE;
+        styles.sgr(&mut w, Color::Default, Color::Default, Style::REVERSE);
+        styles.sgr(&mut w, Color::Default, Color::Default, style);
+        assert_eq!(b"RsU{258}".to_vec(), w);
+
+        // Palette colors are downsampled.
+        w.clear();
+        styles.set_ul_color(&mut w, Color::Palette(196));
+        let red = downsample(Color::Palette(196), 8, Default::default());
+        assert_eq!(Color::Palette(1), red);
+        assert_eq!(b"\x1b[58:5:1m".to_vec(), w);
+    }
+
+    fn initc_desc(with_oc: bool) -> Desc {
+        use tinf::cap::*;
+        if with_oc {
+            desc![
+                "initc", "initc",
+                ccc => true,
+                initc => "<%p1%d:%p2%d,%p3%d,%p4%d>",
+                oc => "O",
+            ]
+        } else {
+            desc![
+                "initc", "initc",
+                ccc => true,
+                initc => "<%p1%d:%p2%d,%p3%d,%p4%d>",
+            ]
+        }
+    }
+
+    #[test]
+    fn palette() {
+        let mut palette = Palette::new(&initc_desc(true));
+        let mut w: Vec<u8> = Vec::new();
+        assert!(palette.set(&mut w, 1, (255, 0, 51)).unwrap());
+        let dynamic = DynamicColor::Background;
+        assert!(!palette.set_dynamic(&mut w, dynamic, (0, 0, 0)).unwrap());
+        palette.reset(&mut w);
+        assert_eq!(b"<1:1000,0,200>O".to_vec(), w);
+
+        // Without oc, initc can't be undone.
+        let mut palette = Palette::new(&initc_desc(false));
+        assert!(!palette.set(&mut w, 1, (255, 0, 51)).unwrap());
+
+        let desc = desc!["xterm-256color", "xterm"];
+        let mut palette = Palette::new(&desc);
+        w.clear();
+        palette.set(&mut w, 12, (255, 0, 51));
+        palette.set_dynamic(&mut w, dynamic, (0, 16, 255));
+        palette.reset(&mut w);
+        palette.reset(&mut w);
+        assert_eq!(
+            b"\x1b]4;12;rgb:ff/00/33\x07\x1b]11;rgb:00/10/ff\x07\
+              \x1b]104\x07\x1b]111\x07"
+                .to_vec(),
+            w
+        );
+    }
+
+    fn cursor_desc() -> Desc {
+        use tinf::cap::*;
+        desc![
+            "cursor", "cursor",
+            UserDef::named("Ss") => "<%p1%d>",
+            UserDef::named("Se") => "E",
+            UserDef::named("Cs") => "[%p1%s]",
+            UserDef::named("Cr") => "R",
+        ]
+    }
+
+    #[test]
+    fn cursor() {
+        let mut cursor = Cursor::new(&cursor_desc());
+        let mut palette = Palette::new(&cursor_desc());
+        let mut w: Vec<u8> = Vec::new();
+        assert!(cursor.set_shape(&mut w, CursorShape::Bar, true).unwrap());
+        assert!(cursor.set_color(&mut w, (255, 0, 1), &mut palette).unwrap());
+        cursor.reset(&mut w);
+        cursor.reset(&mut w);
+        assert_eq!(b"<5>[rgb:ff/00/01]ER".to_vec(), w);
+
+        let desc = desc!["xterm-256color", "xterm"];
+        let mut cursor = Cursor::new(&desc);
+        let mut palette = Palette::new(&desc);
+        w.clear();
+        cursor.set_shape(&mut w, CursorShape::Block, false);
+        cursor.set_color(&mut w, (0, 0, 0), &mut palette);
+        cursor.reset(&mut w);
+        palette.reset(&mut w);
+        assert_eq!(
+            b"\x1b[2 q\x1b]12;rgb:00/00/00\x07\x1b[0 q\x1b]112\x07".to_vec(),
+            w
+        );
+
+        let mut cursor = Cursor::new(&desc!["dumb", "dumb"]);
+        let shape = CursorShape::Underline;
+        assert!(!cursor.set_shape(&mut w, shape, false).unwrap());
+    }
+
+    fn status_desc() -> Desc {
+        use tinf::cap::*;
+        desc![
+            "status", "status",
+            hs => true,
+            tsl => "<%p1%d:",
+            fsl => ">",
+            dsl => "D",
+        ]
+    }
+
+    #[test]
+    fn title() {
+        let mut title = Title::new(&status_desc());
+        let mut w: Vec<u8> = Vec::new();
+        assert!(title.set(&mut w, 2, "a\x07b").unwrap());
+        assert!(!title.set(&mut w, 1, "icon").unwrap());
+        title.reset(&mut w);
+        assert_eq!(b"<0:ab>D".to_vec(), w);
+
+        let mut title = Title::new(&desc!["xterm-256color", "xterm"]);
+        w.clear();
+        title.set(&mut w, 2, "title");
+        title.set(&mut w, 1, "icon");
+        title.reset(&mut w);
+        assert_eq!(
+            b"\x1b[22;0t\x1b]2;title\x07\x1b]1;icon\x07\x1b[23;0t".to_vec(),
+            w
+        );
+    }
+
+    #[test]
+    fn restore() {
+        let desc = desc!["xterm-256color", "xterm"];
+        let mut palette = Palette::new(&desc);
+        let mut cursor = Cursor::new(&desc);
+        let mut title = Title::new(&desc);
+        let mut w: Vec<u8> = Vec::new();
+        palette.set(&mut w, 1, (0, 0, 0));
+        palette.set(&mut w, 1, (255, 255, 255));
+        cursor.set_shape(&mut w, CursorShape::Bar, false);
+        cursor.set_color(&mut w, (0, 0, 255), &mut palette);
+        title.set(&mut w, 2, "title");
+        palette.reset(&mut w);
+        cursor.reset(&mut w);
+        title.reset(&mut w);
+
+        w.clear();
+        palette.restore(&mut w);
+        cursor.restore(&mut w, &mut palette);
+        title.restore(&mut w);
+        assert_eq!(
+            b"\x1b]4;1;rgb:ff/ff/ff\x07\x1b]12;rgb:00/00/ff\x07\x1b[6 q\
+              \x1b[22;0t\x1b]2;title\x07"
+                .to_vec(),
+            w
+        );
+    }
+
+    fn inline_desc() -> Desc {
+        use tinf::cap::*;
+        desc![
+            "inline", "inline",
+            cr => "\r",
+            cuu1 => "U",
+            cud1 => "D",
+            cuu => "[U%p1%d]",
+            ed => "E",
+            el => "L",
+            cup => "<%p1%d,%p2%d>",
+        ]
+    }
+
+    #[test]
+    fn inline() {
+        let desc = inline_desc();
+        let mut inline = Inline::new(&desc, 3, true);
+        let mut motion = Motion::relative(&desc);
+        let size = Some(WinSize { cols: 80, rows: 2 });
+        let mut w: Vec<u8> = Vec::new();
+        inline.reserve(&mut w, &mut motion, size);
+        assert_eq!(b"\r\nU".to_vec(), w);
+
+        w.clear();
+        motion.move_to(&mut w, (0, 1), inline.size(size));
+        inline.clear(&mut w, &mut motion, size);
+        assert_eq!(b"DUE".to_vec(), w);
+
+        // After a resize, the cursor is assumed to be on the same row.
+        w.clear();
+        motion.move_to(&mut w, (0, 1), inline.size(size));
+        inline.sync(&mut w, &mut motion, size);
+        let size = Some(WinSize { cols: 40, rows: 10 });
+        inline.sync(&mut w, &mut motion, size);
+        assert_eq!(Some((0, 1)), motion.pos());
+        inline.finish(&mut w, &mut motion, size);
+        assert_eq!(b"D\rD\r\n".to_vec(), w);
+
+        let mut inline = Inline::new(&desc, 3, false);
+        w.clear();
+        inline.reserve(&mut w, &mut motion, size);
+        motion.set_pos(None);
+        inline.finish(&mut w, &mut motion, size);
+        assert_eq!(b"\r\n\nUU\rUE".to_vec(), w);
+    }
+
+    #[test]
+    fn inline_cursor() {
+        let desc = inline_desc();
+        let mut inline = Inline::new(&desc, 3, true);
+        let size = Some(WinSize { cols: 80, rows: 24 });
+        // Unknown until the cursor's place in the rows is known.
+        assert_eq!(None, inline.relative((5, 20), None, size));
+        assert_eq!(Some((5, 1)), inline.relative((5, 20), Some((0, 1)), size));
+        assert_eq!(Some((7, 2)), inline.relative((7, 21), None, size));
+        assert_eq!(None, inline.relative((7, 22), None, size));
+        assert_eq!(None, inline.relative((7, 18), None, size));
+    }
+
+    // A terminal on a new pseudo-terminal, and the master and slave
+    // descriptors.
+    fn pty_term(desc: &Desc) -> (Box<Terminal>, libc::c_int, libc::c_int) {
+        pty_term_with(desc, ConnectOptions::new())
+    }
+
+    fn pty_term_with(
+        desc: &Desc,
+        opts: ConnectOptions,
+    ) -> (Box<Terminal>, libc::c_int, libc::c_int) {
+        let (master, slave) = open_pty(WinSize { cols: 80, rows: 24 });
+        let term = opts
+            .desc(desc.clone())
+            .fds(slave, slave)
+            .connect()
+            .unwrap();
+        (term, master, slave)
+    }
+
+    // The master and slave descriptors of a new pseudo-terminal.
+    fn open_pty(size: WinSize) -> (libc::c_int, libc::c_int) {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(0, libc::grantpt(master));
+            assert_eq!(0, libc::unlockpt(master));
+            let mut ws: libc::winsize = ::std::mem::zeroed();
+            ws.ws_col = size.cols;
+            ws.ws_row = size.rows;
+            assert_eq!(0, libc::ioctl(master, libc::TIOCSWINSZ, &ws));
+            let name = libc::ptsname(master);
+            let slave = libc::open(name, libc::O_RDWR | libc::O_NOCTTY);
+            assert!(slave >= 0);
+            (master, slave)
+        }
+    }
+
+    // Run by `connect_tty` in a child process, whose controlling
+    // terminal is a pseudo-terminal, with stdin and stdout redirected.
+    #[test]
+    fn connect_tty_child() {
+        if ::std::env::var_os("TVIS_TTY_CHILD").is_none() {
+            return;
+        }
+        let mut term = ConnectOptions::new()
+            .desc(sgr_desc())
+            .tty(true)
+            .connect()
+            .unwrap();
+        assert!(term.is_tty_input());
+        assert!(term.is_tty_output());
+        let size = WinSize { cols: 50, rows: 20 };
+        assert_eq!(size, term.get_size().unwrap());
+
+        // Once signals are handled, the size is kept until SIGWINCH is
+        // read.
+        assert_eq!(None, term.poll_event(Duration::from_millis(0)).unwrap());
+        assert!(!term.scroll(0, 29, 0).unwrap());
+        let tty = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .unwrap();
+        unsafe {
+            let mut ws: libc::winsize = ::std::mem::zeroed();
+            ws.ws_col = 50;
+            ws.ws_row = 30;
+            assert_eq!(0, libc::ioctl(tty.as_raw_fd(), libc::TIOCSWINSZ, &ws));
+        }
+        assert!(!term.scroll(0, 29, 0).unwrap());
+        let evt = term.poll_event(Duration::from_secs(1)).unwrap();
+        assert_eq!(Some(InputEvent::Repaint), evt);
+        assert!(term.scroll(0, 29, 0).unwrap());
+    }
+
+    #[test]
+    fn connect_tty() {
+        use std::os::unix::process::CommandExt;
+        use std::process::{Command, Stdio};
+
+        let (master, slave) = open_pty(WinSize { cols: 50, rows: 20 });
+        let mut cmd = Command::new(::std::env::current_exe().unwrap());
+        cmd.args(&["--exact", "term::platform::test::connect_tty_child"])
+            .env("TVIS_TTY_CHILD", "1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        unsafe {
+            cmd.pre_exec(move || {
+                libc::setsid();
+                libc::ioctl(slave, libc::TIOCSCTTY, 0);
+                Ok(())
+            });
+        }
+        assert!(cmd.status().unwrap().success());
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    fn write_fd(fd: libc::c_int, bytes: &[u8]) {
+        let ptr = bytes.as_ptr() as *const libc::c_void;
+        assert_eq!(bytes.len() as isize, unsafe {
+            libc::write(fd, ptr, bytes.len())
+        });
+    }
+
+    #[test]
+    fn independent_terminals() {
+        let desc = sgr_desc();
+        let sizes = [
+            WinSize { cols: 80, rows: 24 },
+            WinSize { cols: 40, rows: 10 },
+        ];
+        let ptys: Vec<_> = sizes.iter().map(|&size| open_pty(size)).collect();
+        let mut terms: Vec<_> = ptys
+            .iter()
+            .map(|&(_, slave)| {
+                ConnectOptions::new()
+                    .desc(desc.clone())
+                    .fds(slave, slave)
+                    .connect()
+                    .unwrap()
+            })
+            .collect();
+        write_fd(ptys[0].0, b"a");
+        write_fd(ptys[1].0, b"b");
+        for (i, term) in terms.iter_mut().enumerate().rev() {
+            assert_eq!(sizes[i], term.get_size().unwrap());
+            let c = b"ab"[i];
+            let key = Key::Char(c as char, [c, 0, 0, 0], 1);
+            let evt = term.poll_event(Duration::from_secs(1)).unwrap();
+            assert_eq!(Some(InputEvent::Key(key, Mods::empty())), evt);
+            let evt = term.poll_event(Duration::from_millis(20)).unwrap();
+            assert_eq!(None, evt);
+        }
+        drop(terms);
+        for (master, slave) in ptys {
+            unsafe {
+                libc::close(slave);
+                libc::close(master);
+            }
+        }
+    }
+
+    #[test]
+    fn modify_other_keys_after_kitty_query() {
+        let desc = sgr_desc();
+        let (mut term, master, slave) = pty_term(&desc);
+        assert!(term.enable_modify_other_keys().unwrap());
+        // Only the device attributes query is answered.
+        write_fd(master, b"\x1b[?62c");
+        let flags = KeyboardFlags::DISAMBIGUATE;
+        assert!(!term.set_keyboard_flags(flags).unwrap());
+        write_fd(master, b"\x1b[27;5;49~");
+        let one = Key::Char('1', [b'1', 0, 0, 0], 1);
+        let ctrl_1 = InputEvent::Key(one, Mods::CTRL);
+        let evt = term.poll_event(Duration::from_secs(1)).unwrap();
+        assert_eq!(Some(ctrl_1), evt);
+        drop(term);
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    fn utf8_mouse_after_set_mouse_mode() {
+        use input::{ButtonMotion, MouseButton};
+
+        let desc = sgr_desc();
+        let opts = ConnectOptions::new().mouse_mode(MouseMode::None);
+        let (mut term, master, slave) = pty_term_with(&desc, opts);
+        assert_eq!(None, term.poll_event(Duration::from_millis(0)).unwrap());
+        // The terminal says that UTF-8 mouse mode is set.
+        write_fd(master, b"\x1b[?1005;1$y\x1b[?62c");
+        term.set_mouse_mode(MouseMode::Any).unwrap();
+        write_fd(master, b"\x1b[M \xc4\x80!");
+        let (b, m) = (MouseButton::Left, Mods::empty());
+        let press =
+            InputEvent::Mouse(ButtonMotion::Press, b, m, (223, 0), None);
+        let evt = term.poll_event(Duration::from_secs(1)).unwrap();
+        assert_eq!(Some(press), evt);
+        drop(term);
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "after start_input")]
+    fn poll_after_start_input() {
+        let (tx, _rx) = ::std::sync::mpsc::channel();
+        let opts = ConnectOptions::new().input(tx);
+        let (mut term, _, _) = pty_term_with(&sgr_desc(), opts);
+        term.start_input().unwrap();
+        let _ = term.poll_event(Duration::from_millis(10));
+    }
 }
@@ -1,15 +1,22 @@
 #![cfg(windows)]
 
+use std::env;
 use std::ptr;
 use std::sync::atomic::Ordering;
-use std::sync::mpsc::Sender;
+use std::io;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
 use winapi;
 use kernel32;
 use tvis_util::{ConsoleMode, Handle};
 use tvis_util::size::get_screen_buffer_size;
 use tvis_util::color;
-use input::Event;
-use term::{BoldOrBright, Color, Style, Terminal, UseTruecolor, WinSize, TERM};
+use input::{ConsoleReader, Event, InputEvent};
+use term::{downsample, BoldOrBright, Color, ColorMapping, ConnectOptions,
+           CursorShape, DynamicColor, EscPolicy, EscTimeout, KeyboardFlags,
+           MouseMode, Screen, Style, Terminal, UseTruecolor, WinSize, TERM};
+use term::color::colorfgbg_is_dark;
+use width::{self, AmbiguousWidth, WideAtEdge};
 use {Coords, Error, Result};
 
 // winapi omits this.
@@ -29,6 +36,7 @@ struct Styles {
     fg: Color,
     bg: Color,
     style: Style,
+    mapping: ColorMapping,
 }
 
 impl Styles {
@@ -49,7 +57,8 @@ impl Styles {
             }
             ConsoleMode::Win10 => {
                 styles.colors = (256, 256, use_tc);
-                styles.supported = Style::BOLD | Style::UNDERLINE;
+                styles.supported =
+                    Style::BOLD | Style::UNDERLINE | Style::REVERSE;
             }
             _ => (),
         }
@@ -97,6 +106,9 @@ impl Styles {
         bg: Color,
         style: Style,
     ) -> Result<()> {
+        let (fgs, bgs, tc) = self.colors;
+        let fg = self.map_color(fg, fgs, tc);
+        let bg = self.map_color(bg, bgs, tc);
         match self.mode {
             ConsoleMode::Legacy => self.style_legacy(h, fg, bg, style),
             ConsoleMode::Win10 => self.style_win10(h, fg, bg, style),
@@ -104,6 +116,13 @@ impl Styles {
         }
     }
 
+    fn map_color(&self, color: Color, colors: usize, tc: bool) -> Color {
+        match color {
+            Color::TrueColor(..) if tc => color,
+            _ => downsample(color, colors, self.mapping),
+        }
+    }
+
     fn cidx(c: Color, default: u16) -> u16 {
         match c {
             Color::Default => default,
@@ -153,6 +172,7 @@ impl Styles {
         bg: Color,
         style: Style,
     ) -> Result<()> {
+        let style = style & self.supported;
         let clears = self.style - style;
         let sets = style - self.style;
         if clears.contains(Style::BOLD)
@@ -165,10 +185,18 @@ impl Styles {
                 write_handle(h, "\x1b[24m")?;
                 self.style.remove(Style::UNDERLINE);
             }
+            if clears.contains(Style::REVERSE) {
+                write_handle(h, "\x1b[27m")?;
+                self.style.remove(Style::REVERSE);
+            }
             if sets.contains(Style::BOLD) {
                 write_handle(h, "\x1b[1m")?;
                 self.style.insert(Style::BOLD);
             }
+            if sets.contains(Style::REVERSE) {
+                write_handle(h, "\x1b[7m")?;
+                self.style.insert(Style::REVERSE);
+            }
             if sets.contains(Style::UNDERLINE) {
                 write_handle(h, "\x1b[4m")?;
                 self.style.insert(Style::UNDERLINE);
@@ -257,18 +285,42 @@ pub(in term) struct Term {
     init_out_hndl: winapi::HANDLE,
     init_in_mode: Option<winapi::DWORD>,
     tx: Option<Sender<Box<Event>>>,
+    // Reads input for `poll_event`, which takes events from `events`.
+    console: Option<ConsoleReader>,
+    events: Option<Receiver<Box<Event>>>,
+    input_started: bool,
     cmode: (ConsoleMode, ConsoleMode),
     init_cp: (winapi::UINT, winapi::UINT),
     cursor_height: winapi::DWORD,
     cursor_visibility: bool,
+    ambiguous: AmbiguousWidth,
+    wide_at_edge: WideAtEdge,
+    // Palette, dynamic color, and cursor shape changes, undone on
+    // drop.
+    palette_changed: bool,
+    dynamic: [bool; 3],
+    cursor_shaped: bool,
+    init_title: Option<Vec<u16>>,
+    // In inline mode, the number of rows and whether to keep them on
+    // drop, and the buffer row of the first one.
+    inline: Option<(u16, bool)>,
+    origin: i16,
+    // In inline mode, the user's cursor size and visibility, restored
+    // on drop.
+    init_cursor: Option<(winapi::DWORD, winapi::BOOL)>,
 }
 
 impl Term {
-    pub(in term) fn connect(
-        tx: Option<Sender<Box<Event>>>,
-        use_tc: UseTruecolor,
-        b_b: BoldOrBright,
-    ) -> Result<Box<Terminal>> {
+    // The console is always used, so the `tty` and `signals` options
+    // don't apply.
+    pub(in term) fn connect(opts: ConnectOptions) -> Result<Box<Terminal>> {
+        let ConnectOptions {
+            tx,
+            use_tc,
+            b_b,
+            screen,
+            ..
+        } = opts;
         if TERM.compare_and_swap(false, true, Ordering::SeqCst) {
             panic!("TODO: better singleton panic message");
         }
@@ -284,10 +336,25 @@ impl Term {
             init_out_hndl: Handle::Stdout.win_handle(),
             init_in_mode: None,
             tx,
+            console: None,
+            events: None,
+            input_started: false,
             cmode: (out_mode, Handle::Stdin.console_mode()),
             init_cp: (0, 0),
             cursor_height: 0,
             cursor_visibility: true,
+            ambiguous: Default::default(),
+            wide_at_edge: Default::default(),
+            palette_changed: false,
+            dynamic: [false; 3],
+            cursor_shaped: false,
+            init_title: None,
+            inline: match screen {
+                Screen::Alternate => None,
+                Screen::Inline { rows, keep } => Some((rows.max(1), keep)),
+            },
+            origin: 0,
+            init_cursor: None,
         };
         term.set_mode()?;
         term.set_buffer()?;
@@ -319,6 +386,9 @@ impl Term {
     }
 
     fn set_buffer(&mut self) -> Result<()> {
+        if self.inline.is_some() {
+            return self.reserve_rows();
+        }
         let hndl = unsafe {
             kernel32::CreateConsoleScreenBuffer(
                 winapi::GENERIC_READ | winapi::GENERIC_WRITE,
@@ -350,6 +420,59 @@ impl Term {
         Ok(())
     }
 
+    // In inline mode, draw on the current screen buffer, starting at
+    // the cursor's line and scrolling to make room if needed.
+    fn reserve_rows(&mut self) -> Result<()> {
+        let hndl = self.init_out_hndl;
+        let mut cci: winapi::CONSOLE_CURSOR_INFO =
+            unsafe { ::std::mem::uninitialized() };
+        if 0 == unsafe { kernel32::GetConsoleCursorInfo(hndl, &mut cci) } {
+            return Error::ffi_err("GetConsoleCursorInfo failed");
+        }
+        self.cursor_height = cci.dwSize;
+        self.cursor_visibility = cci.bVisible != 0;
+        self.init_cursor = Some((cci.dwSize, cci.bVisible));
+        self.out_hndl = hndl;
+
+        let rows = self.get_size()?.rows;
+        write_handle(hndl, &format!("\r{}", "\n".repeat(rows as usize - 1)))?;
+        let mut csbi: winapi::CONSOLE_SCREEN_BUFFER_INFO =
+            unsafe { ::std::mem::uninitialized() };
+        if 0 == unsafe {
+            kernel32::GetConsoleScreenBufferInfo(hndl, &mut csbi)
+        } {
+            return Error::ffi_err("GetConsoleScreenBufferInfo failed");
+        }
+        self.origin = csbi.dwCursorPosition.Y - (rows as i16 - 1);
+        ::input::set_inline_rows(self.origin as u16, rows);
+        self.set_cursor((0, 0))
+    }
+
+    // Leave the cursor below the inline rows, or clear them, and put
+    // back the user's cursor.
+    fn finish_inline(&mut self, keep: bool) -> Result<()> {
+        ::input::set_inline_rows(0, 0);
+        if keep {
+            let rows = self.get_size()?.rows;
+            self.set_cursor((0, rows - 1))?;
+            write_handle(self.out_hndl, "\r\n")?;
+        } else {
+            self.clear()?;
+        }
+        if let Some((size, visible)) = self.init_cursor {
+            let cci = winapi::CONSOLE_CURSOR_INFO {
+                dwSize: size,
+                bVisible: visible,
+            };
+            if 0 == unsafe {
+                kernel32::SetConsoleCursorInfo(self.out_hndl, &cci)
+            } {
+                return Error::ffi_err("SetConsoleCursorInfo failed");
+            }
+        }
+        Ok(())
+    }
+
     fn set_cp(&mut self) -> Result<()> {
         unsafe {
             self.init_cp =
@@ -368,6 +491,10 @@ impl Term {
         let size = self.get_size()?;
         let num_blanks: winapi::DWORD = size.rows as u32 * size.cols as u32;
         let mut blanks_written: winapi::DWORD = 0;
+        let start = winapi::COORD {
+            X: 0,
+            Y: self.origin,
+        };
         let mut csbi: winapi::CONSOLE_SCREEN_BUFFER_INFO =
             unsafe { ::std::mem::uninitialized() };
         if 0 == unsafe {
@@ -375,7 +502,7 @@ impl Term {
                 self.out_hndl,
                 32,
                 num_blanks,
-                winapi::COORD { X: 0, Y: 0 },
+                start,
                 &mut blanks_written,
             )
         } {
@@ -391,7 +518,7 @@ impl Term {
                 self.out_hndl,
                 csbi.wAttributes,
                 num_blanks,
-                winapi::COORD { X: 0, Y: 0 },
+                start,
                 &mut blanks_written,
             )
         } {
@@ -430,9 +557,59 @@ impl Terminal for Term {
     }
 
     fn start_input(&mut self) -> Result<()> {
-        ::input::start_threads(
-            self.tx.take().expect("start_input may only be called once"),
-        )
+        let tx = self.tx.take().expect("start_input may only be called once");
+        self.input_started = true;
+        ::input::start_threads(tx)
+    }
+
+    fn poll_event(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<InputEvent>> {
+        let deadline = Instant::now() + timeout;
+        if self.events.is_none() {
+            if self.input_started || self.tx.is_some() {
+                panic!(
+                    "poll_event can't be used with an input channel, or \
+                     after start_input"
+                );
+            }
+            let (tx, rx) = channel();
+            self.console = Some(::input::start_polling(tx)?);
+            self.events = Some(rx);
+        }
+        loop {
+            if let Some(ref events) = self.events {
+                while let Ok(evt) = events.try_recv() {
+                    if let Some(evt) = evt.as_any().downcast_ref() {
+                        return Ok(Some(InputEvent::clone(evt)));
+                    }
+                }
+            }
+            let console = match self.console {
+                Some(ref mut console) => console,
+                None => {
+                    let eof = io::ErrorKind::UnexpectedEof;
+                    return Err(io::Error::new(eof, "end of input").into());
+                }
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            if !console.step(deadline - now)? {
+                self.console = None;
+            }
+        }
+    }
+
+    fn read_event(&mut self) -> Result<InputEvent> {
+        loop {
+            let timeout = Duration::from_secs(3600);
+            if let Some(evt) = self.poll_event(timeout)? {
+                return Ok(evt);
+            }
+        }
     }
 
     fn supported_styles(&self) -> Style {
@@ -445,7 +622,13 @@ impl Terminal for Term {
 
     fn get_size(&self) -> Result<WinSize> {
         match get_screen_buffer_size(self.out_hndl) {
-            Some(ws) => Ok(ws),
+            Some(ws) => Ok(match self.inline {
+                Some((rows, _)) => WinSize {
+                    rows: ::std::cmp::min(rows, ws.rows),
+                    ..ws
+                },
+                None => ws,
+            }),
             None => Error::ffi_err("GetConsoleScreenBufferInfo failed"),
         }
     }
@@ -462,17 +645,157 @@ impl Terminal for Term {
         self.styles.bg
     }
 
+    fn set_underline_color(&mut self, _: Color) -> Result<()> {
+        Ok(())
+    }
+
     fn set_style(&mut self, style: Style, fg: Color, bg: Color) -> Result<()> {
         self.styles.set_style(self.out_hndl, fg, bg, style)
     }
 
+    fn set_color_mapping(&mut self, mapping: ColorMapping) {
+        self.styles.mapping = mapping;
+    }
+
+    fn set_palette(&mut self, index: u8, rgb: (u8, u8, u8)) -> Result<bool> {
+        if self.cmode.0 != ConsoleMode::Win10 {
+            return Ok(false);
+        }
+        let (r, g, b) = rgb;
+        let osc =
+            format!("\x1b]4;{};rgb:{:02x}/{:02x}/{:02x}\x07", index, r, g, b);
+        write_handle(self.out_hndl, &osc)?;
+        self.palette_changed = true;
+        Ok(true)
+    }
+
+    fn set_dynamic_color(
+        &mut self,
+        which: DynamicColor,
+        rgb: (u8, u8, u8),
+    ) -> Result<bool> {
+        if self.cmode.0 != ConsoleMode::Win10 {
+            return Ok(false);
+        }
+        let n = match which {
+            DynamicColor::Foreground => 0,
+            DynamicColor::Background => 1,
+            DynamicColor::Cursor => 2,
+        };
+        let (r, g, b) = rgb;
+        let osc =
+            format!("\x1b]{};rgb:{:02x}/{:02x}/{:02x}\x07", 10 + n, r, g, b);
+        write_handle(self.out_hndl, &osc)?;
+        self.dynamic[n] = true;
+        Ok(true)
+    }
+
+    fn set_query_timeout(&mut self, _: Duration) {}
+
+    fn query_palette(&mut self, _: u8) -> Result<Option<(u8, u8, u8)>> {
+        Ok(None)
+    }
+
+    fn query_dynamic_color(
+        &mut self,
+        _: DynamicColor,
+    ) -> Result<Option<(u8, u8, u8)>> {
+        Ok(None)
+    }
+
+    fn query_cursor(&mut self) -> Result<Option<Coords>> {
+        let mut csbi: winapi::CONSOLE_SCREEN_BUFFER_INFO =
+            unsafe { ::std::mem::uninitialized() };
+        if 0 == unsafe {
+            kernel32::GetConsoleScreenBufferInfo(self.out_hndl, &mut csbi)
+        } {
+            return Ok(None);
+        }
+        let pos = csbi.dwCursorPosition;
+        // Rows are counted from the top of the window, or in inline
+        // mode from the first of the inline rows, like `set_cursor`.
+        let (top, rows) = match self.inline {
+            Some((rows, _)) => (self.origin, rows),
+            None => (csbi.srWindow.Top, 0xffff),
+        };
+        if pos.X < 0 || pos.Y < top || (pos.Y - top) as u16 >= rows {
+            return Ok(None);
+        }
+        Ok(Some((pos.X as u16, (pos.Y - top) as u16)))
+    }
+
+    fn set_keyboard_flags(&mut self, _: KeyboardFlags) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn enable_modify_other_keys(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn set_mouse_pixels(&mut self, _: bool) -> Result<bool> {
+        Ok(false)
+    }
+
+    // Console input has no escape sequences.
+    fn set_esc_timeout(&mut self, _: EscTimeout) {}
+
+    fn set_esc_policy(&mut self, _: EscPolicy) {}
+
+    // Without mouse input, the console's quick edit mode (if it was
+    // on) is restored so that text can be selected.
+    fn set_mouse_mode(&mut self, mode: MouseMode) -> Result<()> {
+        ::input::set_mouse_mode(mode);
+        let init_in_mode = match self.init_in_mode {
+            Some(init_in_mode) => init_in_mode,
+            None => return Ok(()),
+        };
+        let mut in_mode: winapi::DWORD = 0;
+        if 0 == unsafe {
+            kernel32::GetConsoleMode(self.in_hndl, &mut in_mode)
+        } {
+            return Error::ffi_err("GetConsoleMode failed");
+        }
+        let quick_edit = winapi::ENABLE_QUICK_EDIT_MODE;
+        if mode == MouseMode::None {
+            in_mode &= !winapi::ENABLE_MOUSE_INPUT;
+            in_mode |= init_in_mode & quick_edit;
+        } else {
+            in_mode |= winapi::ENABLE_MOUSE_INPUT;
+            if self.cmode.1 == ConsoleMode::Win10 {
+                in_mode &= !quick_edit;
+            }
+        }
+        in_mode |= winapi::ENABLE_EXTENDED_FLAGS;
+        if 0 == unsafe {
+            kernel32::SetConsoleMode(self.in_hndl, in_mode)
+        } {
+            return Error::ffi_err("SetConsoleMode failed");
+        }
+        Ok(())
+    }
+
+    fn is_dark_background(&mut self) -> Result<Option<bool>> {
+        if self.cmode.0 != ConsoleMode::None {
+            // Without the intensity bit, only light gray is light.
+            return Ok(Some(color::default_colors().1 != 7));
+        }
+        Ok(env::var("COLORFGBG")
+            .ok()
+            .and_then(|val| colorfgbg_is_dark(&val)))
+    }
+
     fn set_cursor(&mut self, coords: Coords) -> Result<()> {
         if coords.0 > 32_767 || coords.1 > 32_767 {
             panic!("coords out of range");
         }
+        if let Some((rows, _)) = self.inline {
+            if coords.1 >= rows {
+                return Ok(());
+            }
+        }
         let pos = winapi::COORD {
             X: coords.0 as i16,
-            Y: coords.1 as i16,
+            Y: coords.1 as i16 + self.origin,
         };
         // No error on invalid coordinates, to match Linux behavior
         // (and to prevent unexpected panics if set_cursor gets called
@@ -484,6 +807,35 @@ impl Terminal for Term {
         Ok(())
     }
 
+    fn scroll(&mut self, top: u16, bottom: u16, lines: i32) -> Result<bool> {
+        // Legacy consoles have no scroll regions, and the rows in
+        // inline mode may be anywhere in the buffer.
+        if self.cmode.0 != ConsoleMode::Win10 || self.inline.is_some() {
+            return Ok(false);
+        }
+        let size = self.get_size()?;
+        if top > bottom || bottom >= size.rows {
+            return Ok(false);
+        }
+        if lines == 0 {
+            return Ok(true);
+        }
+        let dir = if lines > 0 { 'S' } else { 'T' };
+        let height = i32::from(bottom - top + 1);
+        let lines_abs = lines.checked_abs().unwrap_or(i32::max_value());
+        write_handle(
+            self.out_hndl,
+            &format!(
+                "\x1b[{};{}r\x1b[{}{}\x1b[r",
+                top + 1,
+                bottom + 1,
+                ::std::cmp::min(lines_abs, height),
+                dir
+            ),
+        )?;
+        Ok(true)
+    }
+
     fn cursor_visible(&mut self, visible: bool) -> Result<()> {
         let cci = winapi::CONSOLE_CURSOR_INFO {
             dwSize: self.cursor_height,
@@ -498,8 +850,102 @@ impl Terminal for Term {
         Ok(())
     }
 
-    fn write(&mut self, text: &str) -> Result<()> {
-        write_handle(self.out_hndl, text)
+    fn set_cursor_shape(
+        &mut self,
+        shape: CursorShape,
+        blink: bool,
+    ) -> Result<bool> {
+        match self.cmode.0 {
+            ConsoleMode::Win10 => {
+                let n = match shape {
+                    CursorShape::Block => 2,
+                    CursorShape::Underline => 4,
+                    CursorShape::Bar => 6,
+                };
+                let n = if blink { n - 1 } else { n };
+                write_handle(self.out_hndl, &format!("\x1b[{} q", n))?;
+                self.cursor_shaped = true;
+                Ok(true)
+            }
+            // The legacy console can only change the cursor height,
+            // and always blinks.
+            ConsoleMode::Legacy => {
+                self.cursor_height = match shape {
+                    CursorShape::Block => 100,
+                    CursorShape::Underline => 25,
+                    CursorShape::Bar => return Ok(false),
+                };
+                let vis = self.cursor_visibility;
+                self.cursor_visible(vis)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn set_cursor_color(&mut self, rgb: (u8, u8, u8)) -> Result<bool> {
+        self.set_dynamic_color(DynamicColor::Cursor, rgb)
+    }
+
+    fn write(&mut self, text: &str) -> Result<u16> {
+        let text = width::visible(text);
+        write_handle(self.out_hndl, &text)?;
+        let cols = width::width(&text, self.ambiguous);
+        Ok(::std::cmp::min(cols, 0xffff) as u16)
+    }
+
+    fn write_clipped(&mut self, text: &str, max_cols: u16) -> Result<u16> {
+        let (text, cols) = width::clip(
+            text,
+            max_cols as usize,
+            self.ambiguous,
+            self.wide_at_edge,
+        );
+        write_handle(self.out_hndl, &text)?;
+        Ok(cols as u16)
+    }
+
+    fn set_width_policy(
+        &mut self,
+        ambiguous: AmbiguousWidth,
+        edge: WideAtEdge,
+    ) {
+        self.ambiguous = ambiguous;
+        self.wide_at_edge = edge;
+    }
+
+    fn set_title(&mut self, title: &str) -> Result<bool> {
+        use std::ffi::OsStr;
+        use std::os::windows::ffi::OsStrExt;
+
+        if self.init_title.is_none() {
+            let mut buf = vec![0u16; 1024];
+            let len = unsafe {
+                kernel32::GetConsoleTitleW(buf.as_mut_ptr(), buf.len() as u32)
+            };
+            buf.truncate(len as usize);
+            buf.push(0);
+            self.init_title = Some(buf);
+        }
+        let title: Vec<u16> = OsStr::new(title)
+            .encode_wide()
+            .filter(|&c| c >= 0x20)
+            .chain(Some(0))
+            .collect();
+        if 0 == unsafe { kernel32::SetConsoleTitleW(title.as_ptr()) } {
+            return Error::ffi_err("SetConsoleTitleW failed");
+        }
+        Ok(true)
+    }
+
+    fn set_icon_name(&mut self, _: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn set_job_control(&mut self, _: bool) {}
+
+    fn suspend(&mut self) -> Result<()> {
+        Ok(())
     }
 
     fn clear(&mut self) -> Result<()> {
@@ -533,6 +979,26 @@ impl Terminal for Term {
 
 impl Drop for Term {
     fn drop(&mut self) {
+        if let Some((_, keep)) = self.inline {
+            let _ = self.finish_inline(keep);
+        }
+        if self.palette_changed {
+            let _ = write_handle(self.out_hndl, "\x1b]104\x07");
+        }
+        if self.cursor_shaped {
+            let _ = write_handle(self.out_hndl, "\x1b[0 q");
+        }
+        if let Some(ref title) = self.init_title {
+            unsafe {
+                kernel32::SetConsoleTitleW(title.as_ptr());
+            }
+        }
+        for n in 0..3 {
+            if self.dynamic[n] {
+                let osc = format!("\x1b]{}\x07", 110 + n);
+                let _ = write_handle(self.out_hndl, &osc);
+            }
+        }
         unsafe {
             kernel32::SetConsoleActiveScreenBuffer(self.init_out_hndl);
             kernel32::SetConsoleOutputCP(self.init_cp.0);
@@ -0,0 +1,173 @@
+//! The display width of text, in terminal columns.
+
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// How to treat characters whose East Asian Width is "Ambiguous".
+///
+/// Terminals typically display these as one column wide, except in
+/// CJK locales where they are often two columns wide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AmbiguousWidth {
+    /// One column wide.
+    Narrow,
+    /// Two columns wide.
+    Wide,
+}
+
+impl Default for AmbiguousWidth {
+    fn default() -> AmbiguousWidth {
+        AmbiguousWidth::Narrow
+    }
+}
+
+/// What to do with a wide character that would be cut in half by the
+/// column limit when clipping text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WideAtEdge {
+    /// Fill the remaining column with a space.
+    Pad,
+    /// Leave the remaining column untouched.
+    Omit,
+}
+
+impl Default for WideAtEdge {
+    fn default() -> WideAtEdge {
+        WideAtEdge::Pad
+    }
+}
+
+/// The width of one grapheme cluster.
+pub fn grapheme_width(grapheme: &str, ambiguous: AmbiguousWidth) -> usize {
+    let mut chars = grapheme.chars();
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return 0,
+    };
+    let width = match ambiguous {
+        AmbiguousWidth::Narrow => c.width(),
+        AmbiguousWidth::Wide => c.width_cjk(),
+    }.unwrap_or(0);
+    // Flags (pairs of regional indicators) and characters followed
+    // by the emoji presentation selector are displayed as emoji.
+    let emoji = if c >= '\u{1f1e6}' && c <= '\u{1f1ff}' {
+        chars.next().is_some()
+    } else {
+        chars.any(|c| c == '\u{fe0f}')
+    };
+    if emoji && width == 1 {
+        2
+    } else {
+        width
+    }
+}
+
+/// The width of `text`, after control characters are made visible.
+pub fn width(text: &str, ambiguous: AmbiguousWidth) -> usize {
+    visible(text)
+        .graphemes(true)
+        .map(|g| grapheme_width(g, ambiguous))
+        .sum()
+}
+
+/// Replaces control characters with visible forms: `^@` through
+/// `^_` for C0 controls, `^?` for DEL, and U+FFFD for C1 controls.
+pub fn visible(text: &str) -> Cow<str> {
+    if !text.chars().any(is_control) {
+        return Cow::Borrowed(text);
+    }
+    let mut vis = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            c if c < ' ' => {
+                vis.push('^');
+                vis.push((c as u8 + 64) as char);
+            }
+            '\x7f' => vis.push_str("^?"),
+            c if is_control(c) => vis.push('\u{fffd}'),
+            c => vis.push(c),
+        }
+    }
+    Cow::Owned(vis)
+}
+
+/// Clips `text` (after control characters are made visible) to at
+/// most `max_cols` columns, without splitting grapheme clusters.
+///
+/// Returns the clipped text and its width.
+pub fn clip(
+    text: &str,
+    max_cols: usize,
+    ambiguous: AmbiguousWidth,
+    edge: WideAtEdge,
+) -> (Cow<str>, usize) {
+    let text = visible(text);
+    let mut cols = 0;
+    let mut end = text.len();
+    let mut pad = false;
+    for (idx, g) in text.grapheme_indices(true) {
+        let gw = grapheme_width(g, ambiguous);
+        if cols + gw > max_cols {
+            end = idx;
+            pad = edge == WideAtEdge::Pad && cols < max_cols;
+            break;
+        }
+        cols += gw;
+    }
+    if end == text.len() {
+        return (text, cols);
+    }
+    let mut clipped = match text {
+        Cow::Borrowed(text) => Cow::Borrowed(&text[..end]),
+        Cow::Owned(mut text) => {
+            text.truncate(end);
+            Cow::Owned(text)
+        }
+    };
+    if pad {
+        clipped.to_mut().push(' ');
+        cols += 1;
+    }
+    (clipped, cols)
+}
+
+fn is_control(c: char) -> bool {
+    c < ' ' || ('\x7f' <= c && c <= '\u{9f}')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clip, visible, width, AmbiguousWidth, WideAtEdge};
+
+    #[test]
+    fn widths() {
+        let narrow = AmbiguousWidth::Narrow;
+        assert_eq!(width("abc", narrow), 3);
+        assert_eq!(width("日本", narrow), 4);
+        assert_eq!(width("e\u{301}", narrow), 1);
+        assert_eq!(width("\u{1f1ef}\u{1f1f5}", narrow), 2);
+        assert_eq!(width("\u{2764}\u{fe0f}", narrow), 2);
+        assert_eq!(width("\u{b1}", narrow), 1);
+        assert_eq!(width("\u{b1}", AmbiguousWidth::Wide), 2);
+    }
+
+    #[test]
+    fn controls() {
+        assert_eq!(visible("a\tb\x1b[m"), "a^Ib^[[m");
+        assert_eq!(visible("\x7f\u{85}"), "^?\u{fffd}");
+        assert_eq!(width("\x1b", AmbiguousWidth::Narrow), 2);
+    }
+
+    #[test]
+    fn clipping() {
+        let narrow = AmbiguousWidth::Narrow;
+        let pad = WideAtEdge::Pad;
+        assert_eq!(clip("abcdef", 4, narrow, pad), ("abcd".into(), 4));
+        assert_eq!(clip("ab", 4, narrow, pad), ("ab".into(), 2));
+        assert_eq!(clip("a日本", 4, narrow, pad), ("a日 ".into(), 4));
+        let omit = WideAtEdge::Omit;
+        assert_eq!(clip("a日本", 4, narrow, omit), ("a日".into(), 3));
+        assert_eq!(clip("e\u{301}e\u{301}", 1, narrow, omit).1, 1);
+    }
+}
@@ -28,7 +28,13 @@ pub enum TerminalMode {
 impl TerminalMode {
     #[cfg(not(windows))]
     pub(super) fn from_handle(handle: Handle) -> TerminalMode {
-        match unsafe { ::libc::isatty(handle.fd()) } {
+        TerminalMode::from_fd(handle.fd())
+    }
+
+    /// The `TerminalMode` for a file descriptor.
+    #[cfg(not(windows))]
+    pub fn from_fd(fd: i32) -> TerminalMode {
+        match unsafe { ::libc::isatty(fd) } {
             0 => TerminalMode::Redir,
             _ => TerminalMode::Term,
         }
@@ -55,9 +55,14 @@ pub fn get_cygwin_size(w: &io::Write, defsz: WinSize) -> WinSize {
 /// The size of the terminal connected to the `handle`.
 #[cfg(not(windows))]
 pub fn get_size(handle: Handle) -> Option<WinSize> {
+    get_fd_size(handle.fd())
+}
+
+/// The size of the terminal open as `fd`.
+#[cfg(not(windows))]
+pub fn get_fd_size(fd: i32) -> Option<WinSize> {
     let win: ::libc::winsize = unsafe { ::std::mem::uninitialized() };
-    let res =
-        unsafe { ::libc::ioctl(handle.fd(), ::libc::TIOCGWINSZ, &win) };
+    let res = unsafe { ::libc::ioctl(fd, ::libc::TIOCGWINSZ, &win) };
     if res != 0 {
         return None;
     }